@@ -3,9 +3,14 @@ mod raw;
 
 use self::Sign::*;
 use self::Type::*;
+use crate::journal_entry::JournalAmount;
+use crate::money::Money;
 use anyhow::{bail, Context, Error, Result};
+use regex::Regex;
+use serde::{Serialize, Serializer};
 use std::{
     convert::{TryFrom, TryInto},
+    fmt,
     str::FromStr,
 };
 
@@ -39,6 +44,43 @@ impl FromStr for Type {
     }
 }
 
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Type::Expense => "Expense",
+            Type::Revenue => "Revenue",
+            Type::Asset => "Asset",
+            Type::Liability => "Liability",
+            Type::Equity => "Equity",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Type {
+    /// Guess the type of an account from well known names
+    pub fn guess(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.contains("payable") || name.contains("credit card") || name.contains("loan") {
+            Some(Liability)
+        } else if name.contains("receivable")
+            || name.contains("checking")
+            || name.contains("savings")
+            || name.contains("cash")
+        {
+            Some(Asset)
+        } else if name.contains("equity") || name.contains("capital") {
+            Some(Equity)
+        } else if name.contains("sales") || name.contains("revenue") || name.contains("income") {
+            Some(Revenue)
+        } else if name.contains("expense") {
+            Some(Expense)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Sign {
     Debit,
@@ -58,6 +100,12 @@ impl Tag {
     }
 }
 
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[macro_export]
 macro_rules! tags {
     ($($tag:expr),*) => {{
@@ -67,11 +115,52 @@ macro_rules! tags {
     }};
 }
 
+/// Account name to filter on, matched exactly unless it's a glob with `*` or `?` wildcards
+/// such as `Bank*`, or a regex prefixed with `re:`
+#[derive(Debug, Clone)]
+pub enum AccountPattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl FromStr for AccountPattern {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        if let Some(re) = pattern.strip_prefix("re:") {
+            let re = Regex::new(re).with_context(|| format!("Invalid account regex {}", re))?;
+            Ok(AccountPattern::Regex(re))
+        } else if pattern.contains(['*', '?']) {
+            let glob = regex::escape(pattern)
+                .replace(r"\*", ".*")
+                .replace(r"\?", ".");
+            let re = Regex::new(&format!("^{}$", glob))
+                .with_context(|| format!("Invalid account glob {}", pattern))?;
+            Ok(AccountPattern::Regex(re))
+        } else {
+            Ok(AccountPattern::Exact(pattern.to_owned()))
+        }
+    }
+}
+
+impl AccountPattern {
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            AccountPattern::Exact(exact) => exact == name,
+            AccountPattern::Regex(re) => re.is_match(name),
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Account {
     pub acc_type: Type,
     pub name: String,
+    /// Account number, e.g. `5100`
+    pub code: Option<String>,
     pub tags: Vec<Tag>,
+    /// Other names the account goes by, such as a bank's code for it
+    pub aliases: Vec<String>,
 }
 
 impl Account {
@@ -79,7 +168,9 @@ impl Account {
         Account {
             name: name.to_owned(),
             acc_type,
+            code: None,
             tags,
+            aliases: Vec::new(),
         }
     }
 
@@ -101,9 +192,35 @@ impl Account {
         !self.is_debit()
     }
 
+    /// Whether `balance` is on the account's normal side, such as a debit for an asset.
+    /// A zero balance is always normal.
+    pub fn is_normal_balance(&self, balance: &JournalAmount) -> bool {
+        match (self.sign(), balance) {
+            (_, amount) if amount.money().signum() == 0 => true,
+            (Debit, JournalAmount::Debit(_)) | (Credit, JournalAmount::Credit(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// `balance` as signed money that is positive when on the account's normal side
+    pub fn natural_balance(&self, balance: &JournalAmount) -> Money {
+        match self.sign() {
+            Debit => balance.to_signed_money(),
+            Credit => -balance.to_signed_money(),
+        }
+    }
+
     pub fn has_tag(&self, tag: &Tag) -> bool {
         self.tags.iter().any(|t| t == tag)
     }
+
+    /// Whether the account has a numeric code within the inclusive range
+    pub fn code_in(&self, (min, max): (u32, u32)) -> bool {
+        self.code
+            .as_ref()
+            .and_then(|code| code.parse::<u32>().ok())
+            .map_or(false, |code| min <= code && code <= max)
+    }
 }
 
 impl TryFrom<raw::Account> for Account {
@@ -118,11 +235,36 @@ impl TryFrom<raw::Account> for Account {
         Ok(Account {
             acc_type,
             name: raw_account.name,
+            code: raw_account.code,
             tags,
+            aliases: raw_account.aliases.unwrap_or_default(),
         })
     }
 }
 
+impl From<&Account> for raw::Account {
+    fn from(account: &Account) -> Self {
+        raw::Account {
+            name: account.name.clone(),
+            code: account.code.clone(),
+            description: None,
+            r#type: account.acc_type.to_string(),
+            tags: if account.tags.is_empty() {
+                None
+            } else {
+                Some(account.tags.iter().map(ToString::to_string).collect())
+            },
+            aliases: Some(account.aliases.clone()).filter(|aliases| !aliases.is_empty()),
+        }
+    }
+}
+
+impl Serialize for Account {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        raw::Account::from(self).serialize(serializer)
+    }
+}
+
 impl FromStr for Account {
     type Err = Error;
 
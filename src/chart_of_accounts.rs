@@ -1,17 +1,39 @@
 use super::account::*;
-use anyhow::{anyhow, Error, Result};
+use super::journal_entry::JournalAmount;
+use super::Ledger;
+use anyhow::{anyhow, bail, Error, Result};
 use async_std::fs::File;
 use async_std::io::BufReader;
 use async_std::prelude::*;
-use futures::{future, TryStreamExt};
+use futures::{future, stream, TryStreamExt};
 use lines_ext::LinesExt;
+use serde::Serialize;
+use std::collections::HashSet;
 
 pub type AccountId = usize;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ChartOfAccounts(Vec<Account>);
 
 impl ChartOfAccounts {
+    pub fn new(accounts: Vec<Account>) -> Self {
+        ChartOfAccounts(accounts)
+    }
+
+    pub fn accounts(&self) -> &[Account] {
+        &self.0
+    }
+
+    /// Serialize as yaml documents separated by `---` in the same format `from_file` reads
+    pub fn to_yaml(&self) -> Result<String> {
+        self.0.iter().try_fold(String::new(), |mut yaml, account| {
+            let doc = serde_yaml::to_string(account)?;
+            yaml.push_str("---\n");
+            yaml.push_str(doc.trim_start_matches("---\n"));
+            Ok(yaml)
+        })
+    }
+
     pub async fn from_file(file: &str) -> Result<Self> {
         let file = File::open(file).await?;
         let accounts: Vec<Account> = BufReader::new(file)
@@ -24,10 +46,106 @@ impl ChartOfAccounts {
         Ok(ChartOfAccounts(accounts))
     }
 
+    /// Read accounts from every file in `dir`, each a list of yaml documents starting with `---`
+    /// like entry files. Account names must be unique across all files. Errors list every file
+    /// that can't be read, every account that can't be parsed and every repeated name.
+    pub async fn from_dir(dir: &str) -> Result<Self> {
+        let ledger = Ledger::new(Some(dir));
+        let files: Vec<(String, String)> = ledger.dir_files(dir.to_owned()).try_collect().await?;
+        let mut errors: Vec<String> = ledger
+            .skipped_files()
+            .into_iter()
+            .map(|(file, err)| format!("Failed to read {}: {}", file, err))
+            .collect();
+        let mut accounts = Vec::new();
+        for (file, content) in files {
+            let docs: Vec<String> = stream::iter(
+                content
+                    .lines()
+                    .map(|line| Ok(line.to_owned()))
+                    .collect::<Vec<std::io::Result<String>>>(),
+            )
+            .chunk_by_line("---")
+            .try_collect()
+            .await?;
+            for doc in docs {
+                match doc.parse::<Account>() {
+                    Ok(account) => accounts.push(account),
+                    Err(err) => errors.push(format!("{}: {:#}", file, err)),
+                }
+            }
+        }
+        let mut names = HashSet::new();
+        for account in accounts.iter() {
+            if !names.insert(account.name.as_str()) {
+                errors.push(format!(
+                    "Account {} is defined more than once",
+                    account.name
+                ));
+            }
+        }
+        if !errors.is_empty() {
+            bail!("{}", errors.join("\n"));
+        }
+        Ok(ChartOfAccounts(accounts))
+    }
+
+    /// Names of the accounts whose balance is on the opposite side to their normal sign
+    pub fn abnormal_balances<'a>(
+        &self,
+        balances: impl IntoIterator<Item = (&'a String, &'a JournalAmount)>,
+    ) -> Result<Vec<&'a str>> {
+        balances
+            .into_iter()
+            .filter_map(|(name, balance)| match self.get(name) {
+                Ok(account) if account.is_normal_balance(balance) => None,
+                Ok(_) => Some(Ok(name.as_str())),
+                Err(err) => Some(Err(err)),
+            })
+            .collect()
+    }
+
+    /// Total of `balances` for each class of account, in the order assets, liabilities, equity,
+    /// revenue and expenses. The totals of a complete ledger net to zero.
+    pub fn class_totals<'a>(
+        &self,
+        balances: impl IntoIterator<Item = (&'a String, &'a JournalAmount)>,
+    ) -> Result<Vec<(Type, JournalAmount)>> {
+        let mut totals: Vec<(Type, JournalAmount)> = [
+            Type::Asset,
+            Type::Liability,
+            Type::Equity,
+            Type::Revenue,
+            Type::Expense,
+        ]
+        .iter()
+        .map(|class| (*class, JournalAmount::default()))
+        .collect();
+        for (name, balance) in balances {
+            let class = self.get(name)?.acc_type;
+            if let Some((_, total)) = totals.iter_mut().find(|(t, _)| *t == class) {
+                total.try_add_assign(*balance)?;
+            }
+        }
+        Ok(totals)
+    }
+
     pub fn get(&self, name: &str) -> Result<&Account> {
         self.0
             .iter()
             .find(|account| account.name == name)
             .ok_or_else(|| anyhow!("Account {} not found", name))
     }
+
+    /// Account named `alias_or_name`, or else the account listing it as an alias
+    pub fn resolve(&self, alias_or_name: &str) -> Option<&Account> {
+        self.0
+            .iter()
+            .find(|account| account.name == alias_or_name)
+            .or_else(|| {
+                self.0
+                    .iter()
+                    .find(|account| account.aliases.iter().any(|alias| alias == alias_or_name))
+            })
+    }
 }
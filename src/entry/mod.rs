@@ -1,11 +1,15 @@
 mod raw;
 
+use super::journal_entry::{JournalAmount, JournalEntry};
 use super::money::Money;
 use anyhow::{bail, Context, Error, Result};
 use chrono::prelude::*;
 use chrono_tz::UTC;
+use num_traits::Zero;
 use rrule::{Frequency, RRule, RRuleProperties};
-use rust_decimal::Decimal;
+use rust_decimal::RoundingStrategy;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
 use std::iter::{self, Iterator};
 use std::str::FromStr;
@@ -14,21 +18,57 @@ use std::str::FromStr;
 #[derive(Debug)]
 pub struct Entry {
     id: String,
+    /// Whether the id was generated because none was given
+    auto_id: bool,
+    /// External document number such as an invoice or bill number
+    reference: Option<String>,
+    /// Comment lines leading the entry's document, without the `#`
+    comments: Vec<String>,
     date: EntryDate,
     body: EntryBody,
+    /// Amounts of a recurring entry taking effect from each date, in date order
+    schedule: Vec<(NaiveDate, Money)>,
+    content_hash: u64,
 }
 
 #[derive(Debug)]
 enum EntryDate {
     SingleDate(NaiveDate),
-    RRule(Box<RRule>),
+    /// Recurring dates along with the fields they were parsed from
+    RRule {
+        rule: Box<RRule>,
+        start: NaiveDate,
+        repeat: String,
+        end: Option<NaiveDate>,
+    },
 }
 
 impl EntryDate {
     fn iter(&self) -> Box<dyn Iterator<Item = NaiveDate> + '_> {
         match self {
             EntryDate::SingleDate(date) => Box::new(iter::once(*date)),
-            EntryDate::RRule(rrule) => Box::new(rrule.into_iter().map(|d| d.date().naive_utc())),
+            EntryDate::RRule { rule, .. } => {
+                Box::new(rule.into_iter().map(|d| d.date().naive_utc()))
+            }
+        }
+    }
+
+    /// Every date, or None if the dates recur without an `end` or a COUNT or UNTIL of their own
+    fn iter_bounded(&self) -> Option<Box<dyn Iterator<Item = NaiveDate> + '_>> {
+        match self {
+            EntryDate::SingleDate(_) => Some(self.iter()),
+            EntryDate::RRule { end: Some(end), .. } => {
+                let end = *end;
+                Some(Box::new(self.iter().take_while(move |d| *d <= end)))
+            }
+            EntryDate::RRule { repeat, .. } => {
+                let repeat = repeat.to_uppercase();
+                if repeat.contains("COUNT=") || repeat.contains("UNTIL=") {
+                    Some(self.iter())
+                } else {
+                    None
+                }
+            }
         }
     }
 }
@@ -39,28 +79,332 @@ pub enum EntryBody {
     PaymentReceived(Payment),
     PurchaseInvoice(Invoice),
     SaleInvoice(Invoice),
+    Split(Split),
+    GeneralJournal(GeneralJournal),
+}
+
+impl EntryBody {
+    /// The entry `type` as written in entry files
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            EntryBody::PaymentSent(_) => "Payment Sent",
+            EntryBody::PaymentReceived(_) => "Payment Received",
+            EntryBody::PurchaseInvoice(_) => "Purchase Invoice",
+            EntryBody::SaleInvoice(_) => "Sales Invoice",
+            EntryBody::Split(_) => "Split",
+            EntryBody::GeneralJournal(_) => "General Journal",
+        }
+    }
+
+    /// Replace the amount of a payment, split or single item invoice
+    fn set_amount(&mut self, amount: Money) -> Result<()> {
+        match self {
+            EntryBody::PaymentSent(payment) | EntryBody::PaymentReceived(payment) => {
+                match payment.accounts.as_mut_slice() {
+                    [account] => account.amount = amount,
+                    _ => bail!("Amount of a payment can only be scheduled from a single account"),
+                }
+                payment.amount = amount
+            }
+            EntryBody::Split(split) => split.amount = amount,
+            EntryBody::GeneralJournal(_) => {
+                bail!("Amount of a general journal entry can't be scheduled")
+            }
+            EntryBody::PurchaseInvoice(invoice) | EntryBody::SaleInvoice(invoice) => {
+                if !invoice.payments.is_empty() {
+                    bail!("Amount of an invoice with an attached payment can't be scheduled");
+                }
+                match invoice.items.as_mut_slice() {
+                    [item] => item.amount = InvoiceItemAmount::Total(amount),
+                    _ => bail!("Amount of an invoice can only be scheduled with a single item"),
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Entry {
     pub fn id(&self) -> String {
         self.id.clone()
     }
+    pub fn reference(&self) -> Option<String> {
+        self.reference.clone()
+    }
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
     pub fn dates(&self, until: NaiveDate) -> impl Iterator<Item = NaiveDate> + '_ {
         self.date.iter().take_while(move |d| *d <= until)
     }
+    /// Every date the entry occurs on, however far ahead, or None if it recurs without end
+    pub fn all_dates(&self) -> Option<impl Iterator<Item = NaiveDate> + '_> {
+        self.date.iter_bounded()
+    }
+    /// The entry occurring only once, on its first date
+    pub fn first_occurrence(mut self) -> Self {
+        let first = self.date.iter().next();
+        if let Some(first) = first {
+            self.date = EntryDate::SingleDate(first);
+        }
+        self
+    }
+    /// Dates the entry occurs on from `from` up to `to`, both inclusive
+    pub fn occurrences_between(&self, from: NaiveDate, to: NaiveDate) -> Vec<NaiveDate> {
+        self.date
+            .iter()
+            .skip_while(|d| *d < from)
+            .take_while(|d| *d <= to)
+            .collect()
+    }
     pub fn body(&self) -> EntryBody {
         self.body.clone()
     }
+
+    /// Memo of the entry if given
+    pub fn memo(&self) -> Option<String> {
+        match &self.body {
+            EntryBody::PaymentSent(payment) | EntryBody::PaymentReceived(payment) => {
+                payment.memo.clone()
+            }
+            EntryBody::PurchaseInvoice(invoice) | EntryBody::SaleInvoice(invoice) => {
+                invoice.memo.clone()
+            }
+            EntryBody::Split(split) => split.memo.clone(),
+            EntryBody::GeneralJournal(general) => general.memo.clone(),
+        }
+    }
+
+    /// The body of the occurrence on `date`, with the scheduled amount in effect on that date
+    pub fn body_on(&self, date: NaiveDate) -> EntryBody {
+        let mut body = self.body.clone();
+        if let Some((_, amount)) = self.schedule.iter().rev().find(|(from, _)| *from <= date) {
+            // amounts are checked to apply when parsed
+            body.set_amount(*amount).ok();
+        }
+        body
+    }
+
+    /// Whether the id was generated because none was given
+    pub fn has_auto_id(&self) -> bool {
+        self.auto_id
+    }
+
+    /// Hash of the entry's fields that is stable across runs and platforms,
+    /// so the same entry read from two places can be recognised
+    pub fn content_hash(&self) -> u64 {
+        self.content_hash
+    }
+
+    /// Subtotal of invoice items excluding extras, the amount of a payment or split, or the
+    /// debits of a general journal entry
+    pub fn items_subtotal(&self) -> Result<Money> {
+        match &self.body {
+            EntryBody::PurchaseInvoice(invoice) | EntryBody::SaleInvoice(invoice) => {
+                invoice.items_subtotal()
+            }
+            EntryBody::PaymentSent(payment) | EntryBody::PaymentReceived(payment) => {
+                Ok(payment.amount)
+            }
+            EntryBody::Split(split) => Ok(split.amount),
+            EntryBody::GeneralJournal(general) => general.debits(),
+        }
+    }
+
+    /// Grand total of invoice items and extras, the amount of a payment or split, or the debits
+    /// of a general journal entry
+    pub fn total_with_extras(&self) -> Result<Money> {
+        match &self.body {
+            EntryBody::PurchaseInvoice(invoice) | EntryBody::SaleInvoice(invoice) => {
+                invoice.total()
+            }
+            EntryBody::PaymentSent(payment) | EntryBody::PaymentReceived(payment) => {
+                Ok(payment.amount)
+            }
+            EntryBody::Split(split) => Ok(split.amount),
+            EntryBody::GeneralJournal(general) => general.debits(),
+        }
+    }
+
+    /// Magnitude of a single occurrence of the entry, which is its grand total
+    pub fn abs_amount(&self) -> Result<Money> {
+        self.total_with_extras()
+    }
+
+    /// Net amount posted to `account` by a single occurrence of the entry, or None if the entry
+    /// doesn't touch `account`
+    pub fn amount_of_account(&self, account: &str) -> Result<Option<JournalAmount>> {
+        let date = match self.date.iter().next() {
+            Some(date) => date,
+            None => return Ok(None),
+        };
+        Ok(JournalEntry::from_entry_on(self, date)?
+            .into_iter()
+            .filter(|line| line.1 == account)
+            .fold(None, |acc, line| {
+                let mut total = acc.unwrap_or_default();
+                total += line.2;
+                Some(total)
+            }))
+    }
+
+    /// Accounts posted to by a single occurrence of the entry
+    pub fn accounts(&self) -> Result<Vec<String>> {
+        let date = match self.date.iter().next() {
+            Some(date) => date,
+            None => return Ok(Vec::new()),
+        };
+        Ok(JournalEntry::from_entry_on(self, date)?
+            .into_iter()
+            .map(|line| line.1)
+            .collect())
+    }
+
+    /// Like `amount_of_account` but summed across every occurrence up to `until`,
+    /// each with the scheduled amount in effect on its date
+    pub fn amount_of_account_through(
+        &self,
+        account: &str,
+        until: NaiveDate,
+    ) -> Result<Option<JournalAmount>> {
+        self.dates(until).try_fold(None, |acc, date| {
+            JournalEntry::from_entry_on(self, date)?
+                .into_iter()
+                .filter(|line| line.1 == account)
+                .try_fold(acc, |acc: Option<JournalAmount>, line| {
+                    let mut total = acc.unwrap_or_default();
+                    total.try_add_assign(line.2)?;
+                    Ok(Some(total))
+                })
+        })
+    }
 }
 
-impl TryFrom<raw::Entry> for Entry {
-    type Error = Error;
+/// Order of the month and day in entry dates that aren't written as YYYY-MM-DD
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateOrder {
+    MonthFirst,
+    DayFirst,
+}
+
+impl FromStr for DateOrder {
+    type Err = Error;
+    fn from_str(order: &str) -> Result<Self> {
+        match order {
+            "month-first" => Ok(DateOrder::MonthFirst),
+            "day-first" => Ok(DateOrder::DayFirst),
+            _ => bail!(
+                "Invalid date order '{}', expected month-first or day-first",
+                order
+            ),
+        }
+    }
+}
+
+/// Format entries are written in, selected explicitly rather than by file extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryFormat {
+    /// YAML documents separated by `---`
+    Yaml,
+    /// CSV rows under a header row, one entry per row
+    Csv,
+}
+
+impl FromStr for EntryFormat {
+    type Err = Error;
+    fn from_str(format: &str) -> Result<Self> {
+        match format {
+            "yaml" => Ok(EntryFormat::Yaml),
+            "csv" => Ok(EntryFormat::Csv),
+            _ => bail!("Invalid entry format '{}', expected yaml or csv", format),
+        }
+    }
+}
+
+const MONTH_FIRST_FORMATS: &[&str] = &["%m/%d/%Y", "%m-%d-%Y"];
+const DAY_FIRST_FORMATS: &[&str] = &["%d/%m/%Y", "%d-%m-%Y"];
 
-    fn try_from(raw_entry: raw::Entry) -> Result<Self> {
-        let date: NaiveDate = raw_entry.date.parse()?;
-        let end: Option<NaiveDate> = raw_entry.end.clone().map(|s| s.parse()).transpose()?;
-        Ok(Entry {
+/// Parse the value of a date `field` as YYYY-MM-DD or otherwise with the month and day in
+/// `date_order`. Without an order either is accepted unless both give a date and they differ,
+/// such as 03/04/2020.
+pub fn parse_date(field: &str, value: &str, date_order: Option<DateOrder>) -> Result<NaiveDate> {
+    let value = value.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date);
+    }
+    let parse = |formats: &[&str]| {
+        formats
+            .iter()
+            .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+    };
+    match (
+        date_order,
+        parse(MONTH_FIRST_FORMATS),
+        parse(DAY_FIRST_FORMATS),
+    ) {
+        (Some(DateOrder::MonthFirst), Some(date), _)
+        | (Some(DateOrder::DayFirst), _, Some(date)) => Ok(date),
+        (None, Some(month_first), Some(day_first)) if month_first != day_first => bail!(
+            "Ambiguous {} '{}', could be {} or {} depending on the date order",
+            field,
+            value,
+            month_first,
+            day_first
+        ),
+        (None, Some(date), _) | (None, None, Some(date)) => Ok(date),
+        _ => bail!(
+            "Invalid {} '{}', expected YYYY-MM-DD or {}",
+            field,
+            value,
+            match date_order {
+                Some(DateOrder::MonthFirst) => "MM/DD/YYYY",
+                Some(DateOrder::DayFirst) => "DD/MM/YYYY",
+                None => "MM/DD/YYYY or DD/MM/YYYY",
+            }
+        ),
+    }
+}
+
+impl Entry {
+    /// Convert a raw entry that has an id, parsing its dates in `date_order`
+    fn try_from_raw(raw_entry: raw::Entry, date_order: Option<DateOrder>) -> Result<Self> {
+        let date = parse_date("date", &raw_entry.date, date_order)?;
+        let end = raw_entry
+            .end
+            .as_ref()
+            .map(|end| parse_date("end", end, date_order))
+            .transpose()?;
+        let mut schedule = raw_entry
+            .amounts
+            .iter()
+            .flatten()
+            .map(|(from, amount)| {
+                Ok((
+                    parse_date("amounts date", from, date_order)?,
+                    (*amount).try_into()?,
+                ))
+            })
+            .collect::<Result<Vec<(NaiveDate, Money)>>>()?;
+        schedule.sort_by_key(|(from, _)| *from);
+        if raw_entry.party.is_empty() && raw_entry.r#type != "General Journal" {
+            bail!("Party required for {} Entry", raw_entry.r#type);
+        }
+        if !schedule.is_empty() && raw_entry.repeat.is_none() {
+            bail!("Scheduled amounts are only allowed on recurring entries");
+        }
+        // FNV-1a of the normalised yaml so formatting and comments don't matter
+        let content_hash = serde_yaml::to_string(&raw_entry)?
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        let entry = Entry {
             id: raw_entry.id.clone().context("Id missing!")?,
+            auto_id: false,
+            reference: raw_entry.number.clone(),
+            comments: Vec::new(),
+            content_hash,
+            schedule,
             // `date` is single date unless `repeat` is specified then becomes rrule
             // rrule is parsed from optional `repeat` and `end` fields
             // treating string 'monthly' as generic monthly rrule
@@ -75,7 +419,12 @@ impl TryFrom<raw::Entry> for Entry {
                         }))?,
                         rule_str => rule_str.parse()?,
                     };
-                    Ok(EntryDate::RRule(Box::new(ed)))
+                    Ok(EntryDate::RRule {
+                        rule: Box::new(ed),
+                        start: date,
+                        repeat: rule_str,
+                        end,
+                    })
                 },
             )?,
             body: match raw_entry.r#type.as_ref() {
@@ -83,20 +432,273 @@ impl TryFrom<raw::Entry> for Entry {
                 "Payment Received" => Ok(EntryBody::PaymentReceived(raw_entry.try_into()?)),
                 "Purchase Invoice" => Ok(EntryBody::PurchaseInvoice(raw_entry.try_into()?)),
                 "Sales Invoice" => Ok(EntryBody::SaleInvoice(raw_entry.try_into()?)),
+                "Split" => Ok(EntryBody::Split(raw_entry.try_into()?)),
+                "General Journal" => Ok(EntryBody::GeneralJournal(raw_entry.try_into()?)),
                 _ => Err(Error::msg(format!(
                     "{} not a valid Entry type",
                     raw_entry.r#type
                 ))),
             }?,
+        };
+        for (_, amount) in entry.schedule.iter() {
+            entry.body.clone().set_amount(*amount)?;
+        }
+        Ok(entry)
+    }
+}
+
+/// Convert back to the raw form for writing out. Invoices are always written with items,
+/// an item's account only where it differs from the entry's.
+impl From<&Entry> for raw::Entry {
+    fn from(entry: &Entry) -> Self {
+        let (date, repeat, end) = match &entry.date {
+            EntryDate::SingleDate(date) => (*date, None, None),
+            EntryDate::RRule {
+                start, repeat, end, ..
+            } => (*start, Some(repeat.clone()), *end),
+        };
+        let mut raw_entry = raw::Entry {
+            id: if entry.auto_id {
+                None
+            } else {
+                Some(entry.id.clone())
+            },
+            r#type: entry.body.type_name().to_string(),
+            number: entry.reference.clone(),
+            date: date.to_string(),
+            party: String::new(),
+            account: String::new(),
+            memo: None,
+            amount: None,
+            items: None,
+            extras: None,
+            payment: None,
+            invoice: None,
+            to: None,
+            rounding: None,
+            repeat,
+            end: end.map(|end| end.to_string()),
+            amounts: Some(
+                entry
+                    .schedule
+                    .iter()
+                    .map(|(from, amount)| (from.to_string(), amount.to_f64()))
+                    .collect(),
+            )
+            .filter(|amounts: &BTreeMap<String, f64>| !amounts.is_empty()),
+            checksum: None,
+            lines: None,
+        };
+        match &entry.body {
+            EntryBody::PaymentSent(payment) | EntryBody::PaymentReceived(payment) => {
+                raw_entry.party = payment.party.clone();
+                match payment.accounts.as_slice() {
+                    [account] => raw_entry.account = account.account.clone(),
+                    accounts => raw_entry.payment = raw_payments(accounts),
+                }
+                raw_entry.memo = payment.memo.clone();
+                raw_entry.amount = Some(payment.amount.to_f64());
+                raw_entry.invoice = payment.invoice.clone();
+            }
+            EntryBody::Split(split) => {
+                raw_entry.party = split.party.clone();
+                raw_entry.account = split.from.clone();
+                raw_entry.memo = split.memo.clone();
+                raw_entry.amount = Some(split.amount.to_f64());
+                raw_entry.to = Some(split.to.iter().cloned().collect());
+            }
+            EntryBody::GeneralJournal(general) => {
+                raw_entry.party = general.party.clone().unwrap_or_default();
+                raw_entry.memo = general.memo.clone();
+                raw_entry.lines = Some(
+                    general
+                        .lines
+                        .iter()
+                        .map(|line| raw::Line {
+                            account: line.account.clone(),
+                            amount: line.amount.to_f64(),
+                            party: line.party.clone(),
+                        })
+                        .collect(),
+                );
+            }
+            EntryBody::PurchaseInvoice(invoice) | EntryBody::SaleInvoice(invoice) => {
+                let account = invoice
+                    .items
+                    .first()
+                    .map(|item| item.account.clone())
+                    .unwrap_or_default();
+                raw_entry.party = invoice.party.clone();
+                raw_entry.memo = invoice.memo.clone();
+                raw_entry.items = Some(
+                    invoice
+                        .items
+                        .iter()
+                        .map(|item| {
+                            let (amount, quantity, rate) = match &item.amount {
+                                InvoiceItemAmount::Total(amount) => {
+                                    (Some(amount.to_f64()), None, None)
+                                }
+                                InvoiceItemAmount::ByRate { rate, quantity, .. } => {
+                                    (None, Some(*quantity), Some(rate.to_f64()))
+                                }
+                            };
+                            raw::Item {
+                                description: item.description.clone(),
+                                code: item.code.clone(),
+                                account: Some(item.account.clone())
+                                    .filter(|item_account| *item_account != account),
+                                amount,
+                                quantity,
+                                rate,
+                            }
+                        })
+                        .collect(),
+                );
+                raw_entry.rounding = invoice
+                    .items
+                    .iter()
+                    .find_map(|item| match item.amount {
+                        InvoiceItemAmount::ByRate { rounding, .. } => Some(rounding),
+                        InvoiceItemAmount::Total(_) => None,
+                    })
+                    .or_else(|| {
+                        invoice
+                            .extras
+                            .iter()
+                            .flatten()
+                            .find_map(|extra| match extra.amount {
+                                InvoiceExtraAmount::Rate { rounding, .. } => Some(rounding),
+                                InvoiceExtraAmount::Total(_) => None,
+                            })
+                    })
+                    .filter(|rounding| *rounding != RoundingStrategy::MidpointAwayFromZero)
+                    .and_then(rounding_name);
+                raw_entry.extras = invoice.extras.as_ref().map(|extras| {
+                    extras
+                        .iter()
+                        .map(|extra| {
+                            let (amount, rate) = match extra.amount {
+                                InvoiceExtraAmount::Total(amount) => (Some(amount.to_f64()), None),
+                                InvoiceExtraAmount::Rate { rate, .. } => (None, Some(rate)),
+                            };
+                            raw::Extra {
+                                description: extra.description.clone(),
+                                account: extra.account.clone(),
+                                amount,
+                                rate,
+                            }
+                        })
+                        .collect()
+                });
+                raw_entry.payment = raw_payments(&invoice.payments);
+                raw_entry.account = account;
+            }
+        }
+        raw_entry
+    }
+}
+
+/// Raw form of `payments`, a single payment unless there are several
+fn raw_payments(payments: &[InvoicePayment]) -> Option<raw::Payments> {
+    let mut raw_payments: Vec<_> = payments
+        .iter()
+        .map(|payment| raw::Payment {
+            account: payment.account.clone(),
+            amount: payment.amount.to_f64(),
+        })
+        .collect();
+    match raw_payments.len() {
+        0 => None,
+        1 => raw_payments.pop().map(raw::Payments::One),
+        _ => Some(raw::Payments::Many(raw_payments)),
+    }
+}
+
+impl Serialize for Entry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        raw::Entry::from(self).serialize(serializer)
+    }
+}
+
+/// Parse each `---` separated entry in `content` and write them back out normalised.
+/// Comments leading an entry are kept but other comments and formatting are not.
+pub fn format_docs(content: &str) -> Result<String> {
+    let mut docs = vec![String::new()];
+    for line in content.lines() {
+        if line.trim_end() == "---" {
+            docs.push(String::new());
+        } else if let Some(doc) = docs.last_mut() {
+            doc.push_str(line);
+            doc.push('\n');
+        }
+    }
+    docs.iter()
+        .filter(|doc| !doc.trim().is_empty())
+        .try_fold(String::new(), |mut yaml, doc| {
+            yaml.push_str("---\n");
+            yaml.push_str(&doc.parse::<Entry>()?.to_yaml()?);
+            Ok(yaml)
         })
+}
+
+impl Entry {
+    /// Normalised yaml document of the entry preceded by its comments, without a leading `---`
+    pub fn to_yaml(&self) -> Result<String> {
+        let mut yaml: String = self
+            .comments
+            .iter()
+            .map(|comment| format!("# {}", comment).trim_end().to_string() + "\n")
+            .collect();
+        yaml.push_str(serde_yaml::to_string(self)?.trim_start_matches("---\n"));
+        Ok(yaml)
     }
 }
 
 impl FromStr for Entry {
     type Err = Error;
     fn from_str(doc: &str) -> Result<Self> {
-        let mut raw_entry: raw::Entry = serde_yaml::from_str(doc)
+        Self::parse(doc, None)
+    }
+}
+
+impl Entry {
+    /// Parse an entry from a yaml document with any dates not in YYYY-MM-DD in `date_order`
+    pub fn parse(doc: &str, date_order: Option<DateOrder>) -> Result<Self> {
+        let raw_entry: raw::Entry = serde_yaml::from_str(doc)
             .with_context(|| format!("Failed to deserialize Entry:\n{}", doc))?;
+        let mut entry = Self::from_raw(raw_entry, date_order)?;
+        entry.comments = doc
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| line.is_empty())
+            .take_while(|line| line.starts_with('#'))
+            .map(|line| line.trim_start_matches('#').trim().to_string())
+            .collect();
+        Ok(entry)
+    }
+}
+
+impl Entry {
+    /// Parse entries from CSV with a header row naming the columns `date`, `type`, `party`,
+    /// `account`, `amount` and optionally `memo` and `id`, one entry per row. Dates not in
+    /// YYYY-MM-DD are parsed in `date_order`.
+    pub fn from_csv(
+        reader: impl std::io::Read,
+        date_order: Option<DateOrder>,
+    ) -> impl Iterator<Item = Result<Self>> {
+        csv::Reader::from_reader(reader)
+            .into_deserialize()
+            .enumerate()
+            .map(move |(i, row)| {
+                let row: raw::CsvRow =
+                    row.with_context(|| format!("Failed to deserialize CSV row {}", i + 1))?;
+                Self::from_raw(row.into(), date_order)
+            })
+    }
+
+    /// Convert a raw entry, generating an id if it has none
+    fn from_raw(mut raw_entry: raw::Entry, date_order: Option<DateOrder>) -> Result<Self> {
         let id = format!(
             "{}|{}|{}|{}",
             raw_entry.date,
@@ -104,23 +706,80 @@ impl FromStr for Entry {
             raw_entry.party,
             raw_entry.account // TODO some random uid part
         );
+        let auto_id = raw_entry.id.is_none();
         raw_entry.id.get_or_insert(id.clone());
-        let entry: Entry = raw_entry
-            .try_into()
+        let mut entry = Self::try_from_raw(raw_entry, date_order)
             .with_context(|| format!("Failed to convert Entry: {}", id))?;
+        entry.auto_id = auto_id;
         Ok(entry)
     }
+
+    /// General journal entry on `date` posting each of `lines` as given. The lines must net to
+    /// zero.
+    pub fn from_lines(
+        date: NaiveDate,
+        memo: Option<String>,
+        lines: &[GeneralLine],
+    ) -> Result<Self> {
+        Self::from_raw(
+            raw::Entry {
+                id: None,
+                r#type: "General Journal".to_string(),
+                number: None,
+                date: date.to_string(),
+                party: String::new(),
+                account: String::new(),
+                memo,
+                amount: None,
+                items: None,
+                extras: None,
+                payment: None,
+                to: None,
+                invoice: None,
+                rounding: None,
+                repeat: None,
+                end: None,
+                amounts: None,
+                checksum: None,
+                lines: Some(
+                    lines
+                        .iter()
+                        .map(|line| raw::Line {
+                            account: line.account.clone(),
+                            amount: line.amount.to_f64(),
+                            party: line.party.clone(),
+                        })
+                        .collect(),
+                ),
+            },
+            None,
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Payment {
     pub party: String,
-    pub account: String,
+    /// Accounts paid from, or received into, and the amount of each, summing to `amount`
+    pub accounts: Vec<InvoicePayment>,
     pub memo: Option<String>,
     pub amount: Money,
+    /// Id of the invoice this payment settles, if specified
+    pub invoice: Option<String>,
 }
 
-impl TryFrom<raw::Entry> for Payment {
+/// Moves `amount` out of the `from` account into each of the `to` accounts by percentage
+#[derive(Debug, Clone)]
+pub struct Split {
+    pub party: String,
+    pub from: String,
+    pub memo: Option<String>,
+    pub amount: Money,
+    /// Accounts and the percentage of the amount each receives, summing to 100
+    pub to: Vec<(String, f64)>,
+}
+
+impl TryFrom<raw::Entry> for Split {
     type Error = Error;
 
     fn try_from(
@@ -129,16 +788,151 @@ impl TryFrom<raw::Entry> for Payment {
             account,
             memo,
             amount,
+            to,
             ..
         }: raw::Entry,
     ) -> Result<Self> {
+        if account.is_empty() {
+            bail!("Account required for Split Entry");
+        }
+        let to: Vec<(String, f64)> = to
+            .context("Accounts to split to required for Split Entry")?
+            .into_iter()
+            .collect();
+        if to.iter().any(|(_, percentage)| *percentage <= 0.0) {
+            bail!("Split percentages must be positive");
+        }
+        let total: f64 = to.iter().map(|(_, percentage)| percentage).sum();
+        if (total - 100.0).abs() > 1e-9 {
+            bail!("Split percentages must sum to 100, not {}", total);
+        }
         Ok(Self {
             party,
-            account,
+            from: account,
             memo,
             amount: amount
-                .context("Amount required for Payment Entry")?
+                .context("Amount required for Split Entry")?
                 .try_into()?,
+            to,
+        })
+    }
+}
+
+/// Posts each line to its account as given, such as opening balances or an adjustment
+#[derive(Debug, Clone)]
+pub struct GeneralJournal {
+    /// Party of any line not giving its own
+    pub party: Option<String>,
+    pub memo: Option<String>,
+    /// Lines netting to zero
+    pub lines: Vec<GeneralLine>,
+}
+
+/// Line of a general journal entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneralLine {
+    pub account: String,
+    /// Debits positive and credits negative
+    pub amount: Money,
+    pub party: Option<String>,
+}
+
+impl GeneralJournal {
+    /// Total of the debit lines, which equals that of the credit lines
+    pub fn debits(&self) -> Result<Money> {
+        Money::sum(
+            self.lines
+                .iter()
+                .map(|line| line.amount)
+                .filter(Money::is_positive),
+        )
+    }
+}
+
+impl TryFrom<raw::Entry> for GeneralJournal {
+    type Error = Error;
+
+    fn try_from(
+        raw::Entry {
+            party, memo, lines, ..
+        }: raw::Entry,
+    ) -> Result<Self> {
+        let lines = lines
+            .filter(|lines| !lines.is_empty())
+            .context("Lines required for General Journal Entry")?
+            .into_iter()
+            .map(|line| {
+                Ok(GeneralLine {
+                    account: line.account,
+                    amount: line.amount.try_into()?,
+                    party: line.party,
+                })
+            })
+            .collect::<Result<Vec<GeneralLine>>>()?;
+        let net = Money::sum(lines.iter().map(|line| line.amount))?;
+        if !net.is_zero() {
+            bail!("General Journal lines must net to zero, not {}", net);
+        }
+        Ok(Self {
+            party: Some(party).filter(|party| !party.is_empty()),
+            memo,
+            lines,
+        })
+    }
+}
+
+impl TryFrom<raw::Entry> for Payment {
+    type Error = Error;
+
+    fn try_from(
+        raw::Entry {
+            party,
+            account,
+            memo,
+            amount,
+            invoice,
+            payment,
+            ..
+        }: raw::Entry,
+    ) -> Result<Self> {
+        let (accounts, amount) = match payment {
+            None => {
+                if account.is_empty() {
+                    bail!("Account required for Payment Entry");
+                }
+                let amount: Money = amount
+                    .context("Amount required for Payment Entry")?
+                    .try_into()?;
+                (vec![InvoicePayment { account, amount }], amount)
+            }
+            Some(payments) => {
+                if !account.is_empty() {
+                    bail!("Payment must specify either an account or a list of payments, not both");
+                }
+                let accounts = payments
+                    .into_vec()
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<Vec<InvoicePayment>>>()?;
+                let total = accounts.iter().try_fold(Money::zero(), |total, payment| {
+                    total.try_add(payment.amount)
+                })?;
+                match amount {
+                    Some(amount) if Money::try_from(amount)? != total => bail!(
+                        "Payment amount {} doesn't match the total {} of its payments",
+                        Money::try_from(amount)?,
+                        total
+                    ),
+                    _ => (accounts, total),
+                }
+            }
+        };
+        Ok(Self {
+            party,
+            accounts,
+            memo,
+            amount,
+            invoice,
         })
     }
 }
@@ -146,9 +940,29 @@ impl TryFrom<raw::Entry> for Payment {
 #[derive(Debug, Clone)]
 pub struct Invoice {
     pub party: String,
+    pub memo: Option<String>,
     pub items: Vec<InvoiceItem>,
     pub extras: Option<Vec<InvoiceExtra>>,
-    pub payment: Option<InvoicePayment>,
+    /// Payments attached to the invoice, possibly from several accounts
+    pub payments: Vec<InvoicePayment>,
+}
+
+impl Invoice {
+    /// Sum of all item totals
+    pub fn items_subtotal(&self) -> Result<Money> {
+        self.items
+            .iter()
+            .try_fold(Money::zero(), |acc, item| acc.try_add(item.total()?))
+    }
+
+    /// Items subtotal plus all extras (tax, shipping, etc)
+    pub fn total(&self) -> Result<Money> {
+        let subtotal = self.items_subtotal()?;
+        self.extras
+            .iter()
+            .flatten()
+            .try_fold(subtotal, |acc, extra| acc.try_add(extra.total(subtotal)?))
+    }
 }
 
 fn default_monthly_rrule(date: NaiveDate) -> RRuleProperties {
@@ -166,38 +980,98 @@ impl TryFrom<raw::Entry> for Invoice {
         raw::Entry {
             party,
             account,
+            memo,
+            amount,
             items,
             extras,
             payment,
+            rounding,
+            checksum,
             ..
         }: raw::Entry,
     ) -> Result<Self> {
-        Ok(Self {
-            party,
-            items: items
-                .context("Items not listed on Invoice")?
+        if account.is_empty() {
+            bail!("Account required for Invoice Entry");
+        }
+        let rounding = rounding
+            .as_deref()
+            .map(parse_rounding)
+            .transpose()?
+            .unwrap_or(RoundingStrategy::MidpointAwayFromZero);
+        let to_items = |items: Vec<raw::Item>| {
+            items
                 .into_iter()
                 .map(|mut raw_item| {
                     raw_item.account.get_or_insert(account.clone());
-                    raw_item.try_into()
+                    let mut item: InvoiceItem = raw_item.try_into()?;
+                    if let InvoiceItemAmount::ByRate { rounding: r, .. } = &mut item.amount {
+                        *r = rounding;
+                    }
+                    Ok(item)
                 })
-                .collect::<Result<Vec<InvoiceItem>>>()?,
+                .collect::<Result<Vec<InvoiceItem>>>()
+        };
+        Ok(Self {
+            party,
+            memo: memo.clone(),
+            // an invoice given by a flat `amount` becomes a single item on its account
+            items: match (items, amount) {
+                (Some(items), None) => to_items(items)?,
+                // with `checksum` set, `amount` is an asserted total that must equal the items total
+                (Some(items), Some(amount)) if checksum.unwrap_or(false) => {
+                    let items = to_items(items)?;
+                    let amount: Money = amount.try_into()?;
+                    let total = items
+                        .iter()
+                        .try_fold(Money::zero(), |acc, item| acc.try_add(item.total()?))?;
+                    if total != amount {
+                        bail!(
+                            "Invoice amount {} doesn't match its items total {}, \
+                            a difference of {}",
+                            amount,
+                            total,
+                            amount - total
+                        );
+                    }
+                    items
+                }
+                (None, Some(amount)) => vec![InvoiceItem {
+                    description: memo,
+                    code: None,
+                    account,
+                    amount: InvoiceItemAmount::Total(amount.try_into()?),
+                }],
+                (Some(_), Some(_)) => bail!(
+                    "Invoice must specify either items or amount, not both, \
+                    unless amount is a checksum of the items"
+                ),
+                (None, None) => bail!("Items not listed on Invoice"),
+            },
             extras: extras
                 .map(|extras| {
                     extras
                         .into_iter()
-                        .map(|raw_extra| raw_extra.try_into())
-                        .collect()
+                        .map(|raw_extra| {
+                            let mut extra: InvoiceExtra = raw_extra.try_into()?;
+                            if let InvoiceExtraAmount::Rate { rounding: r, .. } = &mut extra.amount
+                            {
+                                *r = rounding;
+                            }
+                            Ok(extra)
+                        })
+                        .collect::<Result<_>>()
                 })
                 .transpose()?,
-            payment: payment
-                .map(|payment| -> Result<InvoicePayment> {
-                    Ok(InvoicePayment {
-                        account: payment.account,
-                        amount: payment.amount.try_into()?,
-                    })
+            payments: payment
+                .map(|payments| {
+                    payments
+                        .into_vec()
+                        .into_iter()
+                        .map(TryInto::try_into)
+                        .collect()
                 })
-                .transpose()?,
+                .transpose()?
+                .unwrap_or_default(),
         })
     }
 }
@@ -223,6 +1097,7 @@ impl TryFrom<raw::Item> for InvoiceItem {
                 (Some(quantity), Some(rate), None) => InvoiceItemAmount::ByRate {
                     quantity,
                     rate: rate.try_into()?,
+                    rounding: RoundingStrategy::MidpointAwayFromZero,
                 },
                 (None, None, Some(amount)) => InvoiceItemAmount::Total(amount.try_into()?),
                 _ => bail!(
@@ -250,7 +1125,10 @@ impl TryFrom<raw::Extra> for InvoiceExtra {
             account,
             amount: match (amount, rate) {
                 (Some(amount), None) => InvoiceExtraAmount::Total(amount.try_into()?),
-                (None, Some(rate)) => InvoiceExtraAmount::Rate(rate),
+                (None, Some(rate)) => InvoiceExtraAmount::Rate {
+                    rate,
+                    rounding: RoundingStrategy::MidpointAwayFromZero,
+                },
                 (_, _) => bail!("Invoice Extra must specify either amount or rate"),
             },
         })
@@ -270,14 +1148,12 @@ impl InvoiceItem {
         match self.amount.clone() {
             InvoiceItemAmount::Total(amount) => Ok(amount),
             InvoiceItemAmount::ByRate {
-                rate: Money(money),
+                rate,
                 quantity,
+                rounding,
             } => {
-                let quantity: Decimal = quantity.try_into()?;
-                let amount = money
-                    .checked_mul(quantity)
-                    .context("ammount * quantity overflow")?;
-                Ok(Money(amount))
+                // round to cents so the journal balances against payments of whole cents
+                Ok((rate * quantity)?.round_with(2, rounding))
             }
         }
     }
@@ -286,25 +1162,86 @@ impl InvoiceItem {
 #[derive(Debug, Clone)]
 pub enum InvoiceItemAmount {
     Total(Money),
-    ByRate { rate: Money, quantity: f64 },
+    /// Total is rate × quantity rounded to cents with `rounding`
+    ByRate {
+        rate: Money,
+        quantity: f64,
+        rounding: RoundingStrategy,
+    },
+}
+
+/// Name of a `rounding` mode as parsed by `parse_rounding`
+fn rounding_name(rounding: RoundingStrategy) -> Option<String> {
+    match rounding {
+        RoundingStrategy::MidpointAwayFromZero => Some("half-up"),
+        RoundingStrategy::MidpointNearestEven => Some("half-even"),
+        RoundingStrategy::AwayFromZero => Some("up"),
+        RoundingStrategy::ToZero => Some("down"),
+        _ => None,
+    }
+    .map(ToOwned::to_owned)
+}
+
+/// Parse an invoice's `rounding` mode for rate × quantity items and rate extras
+fn parse_rounding(rounding: &str) -> Result<RoundingStrategy> {
+    Ok(match rounding {
+        "half-up" => RoundingStrategy::MidpointAwayFromZero,
+        "half-even" => RoundingStrategy::MidpointNearestEven,
+        "up" => RoundingStrategy::AwayFromZero,
+        "down" => RoundingStrategy::ToZero,
+        _ => bail!(
+            "Invalid rounding '{}', expected one of half-up, half-even, up or down",
+            rounding
+        ),
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct InvoiceExtra {
-    description: Option<String>,
-    account: String,
-    amount: InvoiceExtraAmount,
+    pub description: Option<String>,
+    pub account: String,
+    pub amount: InvoiceExtraAmount,
+}
+
+impl InvoiceExtra {
+    /// Amount of the extra given the subtotal of the items it applies to, a rate rounded to
+    /// cents with the invoice's `rounding`
+    pub fn total(&self, subtotal: Money) -> Result<Money> {
+        match self.amount {
+            InvoiceExtraAmount::Total(amount) => Ok(amount),
+            InvoiceExtraAmount::Rate { rate, rounding } => {
+                Ok((subtotal * rate)?.round_with(2, rounding))
+            }
+        }
+    }
 }
 
+/// Extras are either a fixed amount or a rate applied to the items subtotal (e.g. 0.08 for 8%)
 #[derive(Debug, Clone)]
-enum InvoiceExtraAmount {
+pub enum InvoiceExtraAmount {
     Total(Money),
-    Rate(f64),
+    /// Total is the subtotal × rate rounded to cents with `rounding`
+    Rate {
+        rate: f64,
+        rounding: RoundingStrategy,
+    },
     // CumulativeRate(f64),
 }
 
+/// Amount paid from or into an account
 #[derive(Debug, Clone)]
 pub struct InvoicePayment {
     pub account: String,
     pub amount: Money,
 }
+
+impl TryFrom<raw::Payment> for InvoicePayment {
+    type Error = Error;
+
+    fn try_from(raw::Payment { account, amount }: raw::Payment) -> Result<Self> {
+        Ok(InvoicePayment {
+            account,
+            amount: amount.try_into()?,
+        })
+    }
+}
@@ -1,37 +1,71 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 /// Raw struct deserilized from yaml
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Entry {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub id: Option<String>, // if not specified will use filename
     pub r#type: String,
+    #[serde(alias = "ref", skip_serializing_if = "Option::is_none")]
+    pub number: Option<String>, // external invoice or bill number
     pub date: String,
-    pub party: String,
-    pub account: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub party: String, // may be left out of a general journal entry giving a party per line
+    #[serde(alias = "from", default, skip_serializing_if = "String::is_empty")] // for splits
+    pub account: String, // may be left out of a payment listing the accounts it's paid from
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub memo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub items: Option<Vec<Item>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub extras: Option<Vec<Extra>>,
-    pub payment: Option<Payment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payment: Option<Payments>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<BTreeMap<String, f64>>, // percentage of a split each account receives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<String>, // id of invoice a payment settles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rounding: Option<String>, // of rate × quantity items and rate extras: half-up (default), half-even, up, down
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub repeat: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub end: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amounts: Option<BTreeMap<String, f64>>, // amount of occurrences from each date on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<bool>, // invoice amount is the asserted total of its items
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<Line>>, // of a general journal entry
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Item {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
-    pub code: Option<String>,    // include if tracking
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>, // include if tracking
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub account: Option<String>, // include if specific override to default above
-    pub amount: Option<f64>,     // specify either ammount here or quantity and rate below
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<f64>, // specify either ammount here or quantity and rate below
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub quantity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub rate: Option<f64>,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Extra {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     pub account: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub amount: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub rate: Option<f64>,
 }
 
@@ -40,3 +74,70 @@ pub struct Payment {
     pub account: String,
     pub amount: f64,
 }
+
+/// Line of a general journal entry, debits positive and credits negative
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Line {
+    pub account: String,
+    pub amount: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub party: Option<String>, // if other than the entry's
+}
+
+/// A single payment or a list of payments from (or into) several accounts
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Payments {
+    One(Payment),
+    Many(Vec<Payment>),
+}
+
+impl Payments {
+    pub fn into_vec(self) -> Vec<Payment> {
+        match self {
+            Payments::One(payment) => vec![payment],
+            Payments::Many(payments) => payments,
+        }
+    }
+}
+
+/// Row of a spreadsheet of simple entries with a header row naming the columns
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+pub struct CsvRow {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub date: String,
+    pub r#type: String,
+    pub party: String,
+    pub account: String,
+    pub amount: f64,
+    #[serde(default)]
+    pub memo: Option<String>,
+}
+
+impl From<CsvRow> for Entry {
+    fn from(row: CsvRow) -> Self {
+        Entry {
+            id: row.id,
+            r#type: row.r#type,
+            number: None,
+            date: row.date,
+            party: row.party,
+            account: row.account,
+            // empty cells are read as empty strings
+            memo: row.memo.filter(|memo| !memo.is_empty()),
+            amount: Some(row.amount),
+            items: None,
+            extras: None,
+            payment: None,
+            to: None,
+            invoice: None,
+            rounding: None,
+            repeat: None,
+            end: None,
+            amounts: None,
+            checksum: None,
+            lines: None,
+        }
+    }
+}
@@ -1,17 +1,19 @@
 #![allow(clippy::new_without_default)]
 use self::JournalAmount::*;
 use super::account::Sign;
-use super::entry::{Entry, EntryBody, Invoice};
+use super::entry::{Entry, EntryBody, Invoice, Payment};
 use super::money::Money;
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use chrono::prelude::*;
 use num_traits::Zero;
-use std::convert::TryFrom;
+use rust_decimal::prelude::*;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
 use std::fmt;
-use std::ops::AddAssign;
+use std::ops::{AddAssign, Neg, Sub};
 
 pub type JournalAccount = String;
 pub type JournalParty = Option<String>;
+pub type JournalReference = Option<String>;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum JournalAmount {
@@ -29,17 +31,151 @@ impl JournalAmount {
     pub fn new() -> Self {
         Debit(Money::zero())
     }
+
+    /// Treat negative money as a credit and anything else (including zero) as a debit
+    pub fn from_signed_money(money: Money) -> Self {
+        if money.is_negative() {
+            Credit(-money)
+        } else {
+            Debit(money)
+        }
+    }
+
+    /// Net of `amounts`, debits against credits, zero if there are none, erroring on overflow
+    pub fn sum<I: IntoIterator<Item = JournalAmount>>(amounts: I) -> Result<JournalAmount> {
+        amounts
+            .into_iter()
+            .try_fold(JournalAmount::default(), |mut acc, amount| {
+                acc.try_add_assign(amount)?;
+                Ok(acc)
+            })
+    }
+
+    /// Debits as positive and credits as negative money
+    pub fn to_signed_money(&self) -> Money {
+        match *self {
+            Debit(money) => money,
+            Credit(money) => -money,
+        }
+    }
+
+    /// Add in place without panicking, erroring on overflow
+    pub fn try_add_assign(&mut self, other: Self) -> Result<()> {
+        let mut total = self.to_signed_money();
+        total.try_add_assign(other.to_signed_money())?;
+        *self = Self::from_signed_money(total);
+        Ok(())
+    }
 }
 
-impl fmt::Display for JournalAmount {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+/// Layout of the debit and credit columns when rendering amounts as rows
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RowFormat {
+    /// Width of the part of an amount before the decimal point
+    pub int_width: usize,
+    /// Width of the part of an amount from the decimal point on
+    pub frac_width: usize,
+    /// Color debits green and credits red
+    pub color: bool,
+    /// Show zero amounts as a dash in both columns
+    pub zero_dash: bool,
+}
+
+impl RowFormat {
+    /// Minimum width of each column
+    const MIN_WIDTH: usize = 12;
+
+    /// Fit the columns to the given amounts so their decimal points line up
+    pub fn fit<'a>(amounts: impl IntoIterator<Item = &'a JournalAmount>) -> Self {
+        Self::fit_money(amounts.into_iter().map(JournalAmount::money))
+    }
+
+    /// Fit to the given amounts of money, such as a column of report totals
+    pub fn fit_money(amounts: impl IntoIterator<Item = Money>) -> Self {
+        amounts
+            .into_iter()
+            .fold(Self::default(), |mut format, money| {
+                let (int, frac) = split_decimal(money);
+                format.int_width = format.int_width.max(int.len());
+                format.frac_width = format.frac_width.max(frac.len());
+                format
+            })
+    }
+
+    fn width(&self) -> usize {
+        Self::MIN_WIDTH.max(self.int_width + self.frac_width)
+    }
+
+    /// `money` padded either side of its decimal point to line up with the amounts fitted
+    pub fn align(&self, money: Money) -> String {
+        let (int, frac) = split_decimal(money);
+        format!(
+            "{:>iw$}{:<fw$}",
+            int,
+            frac,
+            iw = self.int_width,
+            fw = self.frac_width
+        )
+    }
+
+    fn cell(&self, money: Money) -> String {
+        format!("{:>w$}", self.align(money), w = self.width())
+    }
+}
+
+/// Split the display of money at the decimal point
+fn split_decimal(money: Money) -> (String, String) {
+    let s = money.to_string();
+    match s.find('.') {
+        Some(i) => (s[..i].to_owned(), s[i..].to_owned()),
+        None => (s, String::new()),
+    }
+}
+
+impl JournalAmount {
+    /// The unsigned money of either side
+    pub fn money(&self) -> Money {
+        match self {
+            Debit(money) | Credit(money) => *money,
+        }
+    }
+
+    /// Render as debit and credit columns according to the given format
+    pub fn to_row_string(&self, format: &RowFormat) -> String {
+        let blank = " ".repeat(format.width());
+        if format.zero_dash && self.money().is_zero() {
+            let dash = format!("{:>w$}", "—", w = format.width());
+            return format!("{} | {}", dash, dash);
+        }
         match self {
-            Self::Debit(debit) => write!(f, "{:>12} |             ", debit.to_string()),
-            Self::Credit(credit) => write!(f, "             | {:>12}", credit.to_string()),
+            Debit(debit) => {
+                let cell = format.cell(*debit);
+                let cell = if format.color {
+                    format!("\x1b[32m{}\x1b[0m", cell)
+                } else {
+                    cell
+                };
+                format!("{} | {}", cell, blank)
+            }
+            Credit(credit) => {
+                let cell = format.cell(*credit);
+                let cell = if format.color {
+                    format!("\x1b[31m{}\x1b[0m", cell)
+                } else {
+                    cell
+                };
+                format!("{} | {}", blank, cell)
+            }
         }
     }
 }
 
+impl fmt::Display for JournalAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_row_string(&RowFormat::default()))
+    }
+}
+
 impl AddAssign for JournalAmount {
     fn add_assign(&mut self, other: Self) {
         // treat credit amount as negative to add
@@ -51,21 +187,51 @@ impl AddAssign for JournalAmount {
             Debit(money) => money,
             Credit(money) => -money,
         };
-        let sum = relative_self + relative_other;
-        if sum >= Money::zero() {
-            *self = Debit(sum)
-        } else {
-            *self = Credit(-sum)
+        *self = Self::from_signed_money(relative_self + relative_other)
+    }
+}
+
+/// Serialized as `debit` and `credit` fields with the other side null
+impl Serialize for JournalAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (debit, credit) = match self {
+            Debit(money) => (Some(money), None),
+            Credit(money) => (None, Some(money)),
+        };
+        let mut amount = serializer.serialize_struct("JournalAmount", 2)?;
+        amount.serialize_field("debit", &debit)?;
+        amount.serialize_field("credit", &credit)?;
+        amount.end()
+    }
+}
+
+impl Neg for JournalAmount {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        match self {
+            Debit(money) => Credit(money),
+            Credit(money) => Debit(money),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Sub for JournalAmount {
+    type Output = Self;
+
+    fn sub(mut self, other: Self) -> Self {
+        self += -other;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct JournalEntry(
     pub NaiveDate,
     pub JournalAccount,
     pub JournalAmount,
     pub JournalParty,
+    pub JournalReference,
 );
 
 impl JournalEntry {
@@ -76,51 +242,131 @@ impl JournalEntry {
         });
         Ok(entry
             .dates(until)
-            .map(|date| match entry.body() {
-                EntryBody::PurchaseInvoice(invoice) => {
-                    Self::entries_from_invoice(invoice, date, Sign::Debit)
-                }
-
-                EntryBody::PaymentSent(payment) => Ok(vec![
-                    JournalEntry(
-                        date,
-                        payment.account,
-                        Credit(payment.amount),
-                        Some(payment.party.clone()),
-                    ),
-                    JournalEntry(
-                        date,
-                        String::from("Accounts Payable"),
-                        Debit(payment.amount),
-                        Some(payment.party),
-                    ),
-                ]),
-
-                EntryBody::SaleInvoice(invoice) => {
-                    Self::entries_from_invoice(invoice, date, Sign::Credit)
-                }
-
-                EntryBody::PaymentReceived(payment) => Ok(vec![
-                    JournalEntry(
-                        date,
-                        payment.account,
-                        Debit(payment.amount),
-                        Some(payment.party.clone()),
-                    ),
-                    JournalEntry(
-                        date,
-                        String::from("Accounts Receivable"),
-                        Credit(payment.amount),
-                        Some(payment.party),
-                    ),
-                ]),
-            })
+            .map(|date| Self::from_entry_on(&entry, date))
             .collect::<Result<Vec<Vec<Self>>>>()?
             .into_iter()
             .flatten()
             .collect::<Vec<Self>>())
     }
 
+    /// Journal entries of a single occurrence of `entry` on `date`
+    pub fn from_entry_on(entry: &Entry, date: NaiveDate) -> Result<Vec<Self>> {
+        let mut lines = match entry.body_on(date) {
+            EntryBody::PurchaseInvoice(invoice) => {
+                Self::entries_from_invoice(invoice, date, Sign::Debit)
+            }
+
+            EntryBody::PaymentSent(payment) => Ok(Self::entries_from_payment(
+                payment,
+                date,
+                "Accounts Payable",
+                Credit,
+            )),
+
+            EntryBody::SaleInvoice(invoice) => {
+                Self::entries_from_invoice(invoice, date, Sign::Credit)
+            }
+
+            EntryBody::Split(split) => Self::balanced_across(
+                vec![JournalEntry(
+                    date,
+                    split.from,
+                    Credit(split.amount),
+                    Some(split.party),
+                    None,
+                )],
+                split.to,
+            ),
+
+            EntryBody::PaymentReceived(payment) => Ok(Self::entries_from_payment(
+                payment,
+                date,
+                "Accounts Receivable",
+                Debit,
+            )),
+
+            EntryBody::GeneralJournal(general) => {
+                let party = general.party;
+                Ok(general
+                    .lines
+                    .into_iter()
+                    .map(|line| {
+                        JournalEntry(
+                            date,
+                            line.account,
+                            JournalAmount::from_signed_money(line.amount),
+                            line.party.or_else(|| party.clone()),
+                            None,
+                        )
+                    })
+                    .collect())
+            }
+        }?;
+        let reference = entry.reference();
+        for line in &mut lines {
+            line.4 = reference.clone();
+        }
+        Ok(lines)
+    }
+
+    /// A line for each account of `payment` on the `side` given, against `contra_account` for
+    /// the total
+    fn entries_from_payment(
+        payment: Payment,
+        date: NaiveDate,
+        contra_account: &str,
+        side: fn(Money) -> JournalAmount,
+    ) -> Vec<Self> {
+        let party = Some(payment.party);
+        let mut entries: Vec<Self> = payment
+            .accounts
+            .into_iter()
+            .map(|paid| JournalEntry(date, paid.account, side(paid.amount), party.clone(), None))
+            .collect();
+        entries.push(JournalEntry(
+            date,
+            contra_account.to_string(),
+            -side(payment.amount),
+            party,
+            None,
+        ));
+        entries
+    }
+
+    /// Balance `lines` by spreading whatever they're out of balance by across `accounts` in
+    /// proportion to each account's weight. Shares are rounded to cents with the leftover cents
+    /// going to the largest remainders so the result balances exactly.
+    /// New lines take the date, party and reference of the first line.
+    pub fn balanced_across(
+        mut lines: Vec<Self>,
+        accounts: Vec<(JournalAccount, f64)>,
+    ) -> Result<Vec<Self>> {
+        let first = lines.first().context("No journal lines to balance")?;
+        let (date, party, reference) = (first.0, first.3.clone(), first.4.clone());
+        let residual = net(&lines)?;
+        let contra_amount_contructor: fn(Money) -> JournalAmount = match residual {
+            Debit(_) => Credit,
+            Credit(_) => Debit,
+        };
+        let weights = accounts
+            .iter()
+            .map(|(_, weight)| Decimal::from_f64(*weight).context("Invalid weight"))
+            .collect::<Result<Vec<Decimal>>>()?;
+        let shares = residual.money().allocate(&weights)?;
+        for ((account, _), share) in accounts.into_iter().zip(shares) {
+            if !share.is_zero() {
+                lines.push(JournalEntry(
+                    date,
+                    account,
+                    contra_amount_contructor(share),
+                    party.clone(),
+                    reference.clone(),
+                ));
+            }
+        }
+        Ok(lines)
+    }
+
     fn entries_from_invoice(
         invoice: Invoice,
         date: NaiveDate,
@@ -133,6 +379,14 @@ impl JournalEntry {
             Sign::Debit => (Debit, Credit),
             Sign::Credit => (Credit, Debit),
         };
+        // a negative item or extra such as a discount is journaled on the opposite side
+        let item_amount = |money: Money| {
+            if money.is_negative() {
+                contra_amount_contructor(-money)
+            } else {
+                amount_contructor(money)
+            }
+        };
         let mut entries = invoice
             .items
             .iter()
@@ -140,45 +394,161 @@ impl JournalEntry {
                 Ok(JournalEntry(
                     date,
                     item.account.clone(),
-                    amount_contructor(item.total()?),
+                    item_amount(item.total()?),
                     Some(invoice.party.clone()),
+                    None,
                 ))
             })
             .collect::<Result<Vec<Self>>>()?; // TODO include inventory entries if tracking
-        let contra_amount = contra_amount_contructor(
-            invoice
-                .items
-                .iter()
-                .fold(Money::try_from(0.0), |acc, item| Ok(acc? + item.total()?))?,
-        );
+        let subtotal = invoice.items_subtotal()?;
+        for extra in invoice.extras.iter().flatten() {
+            entries.push(JournalEntry(
+                date,
+                extra.account.clone(),
+                item_amount(extra.total(subtotal)?),
+                Some(invoice.party.clone()),
+                None,
+            ));
+        }
+        let contra_amount = -item_amount(invoice.total()?);
         let contra_account = match sign {
             Sign::Debit => String::from("Accounts Payable"),
             Sign::Credit => String::from("Accounts Receivable"),
         };
-        let contra_entry = match invoice.payment {
-            None => JournalEntry(
+        // attached payments settle their amounts directly from each payment account
+        // and any remainder (owed or overpaid) is left on the contra account
+        let mut contra_remaining = contra_amount;
+        for payment in invoice.payments {
+            let paid = contra_amount_contructor(payment.amount);
+            entries.push(JournalEntry(
                 date,
-                contra_account,
-                contra_amount,
+                payment.account,
+                paid,
                 Some(invoice.party.clone()),
-            ),
-            // TODO this doesn't appear to take into account payment amount separate from
-            // contra_amount
-            Some(payment) => JournalEntry(
+                None,
+            ));
+            contra_remaining = contra_remaining - paid;
+        }
+        if !contra_remaining.money().is_zero() {
+            entries.push(JournalEntry(
                 date,
-                payment.account,
-                contra_amount,
+                contra_account,
+                contra_remaining,
                 Some(invoice.party.clone()),
-            ),
-        };
-        entries.push(contra_entry);
+                None,
+            ));
+        }
         Ok(entries)
     }
 }
 
+/// Serialized with the amount as `debit` and `credit` fields like `JournalAmount`
+impl Serialize for JournalEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Self(date, account, amount, party, reference) = self;
+        let (debit, credit) = match amount {
+            Debit(money) => (Some(money), None),
+            Credit(money) => (None, Some(money)),
+        };
+        let mut line = serializer.serialize_struct("JournalEntry", 6)?;
+        line.serialize_field("date", &date.to_string())?;
+        line.serialize_field("account", account)?;
+        line.serialize_field("debit", &debit)?;
+        line.serialize_field("credit", &credit)?;
+        line.serialize_field("party", party)?;
+        line.serialize_field("reference", reference)?;
+        line.end()
+    }
+}
+
 impl fmt::Display for JournalEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Self(date, account, amount, _) = self;
-        write!(f, "{} | {:25} | {}", date, account.to_string(), amount)
+        let Self(date, account, amount, _, reference) = self;
+        write!(f, "{} | {:25} | {}", date, account.to_string(), amount)?;
+        if let Some(reference) = reference {
+            write!(f, " | #{}", reference)?;
+        }
+        Ok(())
+    }
+}
+
+/// The journal lines of a single occurrence of an entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalGroup {
+    pub id: String,
+    pub date: NaiveDate,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+    pub lines: Vec<JournalEntry>,
+}
+
+impl JournalGroup {
+    /// A group for each occurrence of `entry` dated up to `until` (defaults to today)
+    pub fn from_entry(entry: &Entry, until: Option<NaiveDate>) -> Result<Vec<Self>> {
+        let until = until.unwrap_or({
+            let today = Local::today();
+            NaiveDate::from_ymd(today.year(), today.month(), today.day())
+        });
+        entry
+            .dates(until)
+            .map(|date| {
+                Ok(Self {
+                    id: entry.id(),
+                    date,
+                    memo: entry.memo(),
+                    reference: entry.reference(),
+                    lines: JournalEntry::from_entry_on(entry, date)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Net of all lines, which is zero when the entry balances
+    pub fn total(&self) -> Result<JournalAmount> {
+        net(&self.lines)
+    }
+
+    /// Error with the amount the entry is out of balance by unless it balances
+    pub fn assert_balanced(&self) -> Result<()> {
+        match self.total()? {
+            total if total.money().is_zero() => Ok(()),
+            Debit(money) => bail!(
+                "Entry {} on {} is out of balance by a debit of {}",
+                self.id,
+                self.date,
+                money
+            ),
+            Credit(money) => bail!(
+                "Entry {} on {} is out of balance by a credit of {}",
+                self.id,
+                self.date,
+                money
+            ),
+        }
+    }
+}
+
+/// Net of journal `lines`, debits less credits
+fn net(lines: &[JournalEntry]) -> Result<JournalAmount> {
+    JournalAmount::sum(lines.iter().map(|line| line.2))
+}
+
+/// Header of date, memo (or id) and any reference number followed by the indented lines
+impl fmt::Display for JournalGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} | {}",
+            self.date,
+            self.memo.as_ref().unwrap_or(&self.id)
+        )?;
+        match &self.reference {
+            Some(reference) => writeln!(f, " | #{}", reference)?,
+            None => writeln!(f)?,
+        }
+        for JournalEntry(_, account, amount, ..) in &self.lines {
+            writeln!(f, "    {:25} | {}", account.to_string(), amount)?;
+        }
+        Ok(())
     }
 }
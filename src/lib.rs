@@ -5,100 +5,1010 @@ pub mod journal_entry;
 pub mod money;
 pub mod report;
 
-use anyhow::{Error, Result};
-use async_std::fs::File;
+use account::{Account, AccountPattern, Tag, Type};
+use anyhow::{bail, Context, Error, Result};
+use async_std::fs;
 use async_std::io::prelude::*;
 use async_std::io::{stdin, BufReader};
 use async_walkdir::{DirEntry, WalkDir};
 use chart_of_accounts::ChartOfAccounts;
-use entry::Entry;
+use chrono::prelude::*;
+use entry::{DateOrder, Entry, EntryBody, EntryFormat, GeneralLine, Invoice, Payment};
 use futures::future::{self, Future};
-use futures::stream::{self, Stream, StreamExt, TryStreamExt};
-use journal_entry::{JournalAccount, JournalAmount, JournalEntry};
+use futures::stream::{self, BoxStream, Stream, StreamExt, TryStreamExt};
+use journal_entry::{JournalAccount, JournalAmount, JournalEntry, JournalGroup};
 use lines_ext::LinesExt;
+use money::Money;
+use num_traits::Zero;
 use report::ReportNode;
 use std::borrow::ToOwned;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::ErrorKind;
-use std::ops::AddAssign;
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 pub struct Ledger {
     dir: Option<String>,
+    /// Drop entries whose id has already been seen
+    dedup: bool,
+    /// Only read entries with this reference number
+    reference: Option<String>,
+    /// Take only the first occurrence of recurring entries
+    no_recurring: bool,
+    /// Month and day the fiscal year starts on
+    fiscal_year_start: (u32, u32),
+    /// Order of the month and day in entry dates not written as YYYY-MM-DD
+    date_order: Option<DateOrder>,
+    /// Format of the entries read from own dir, file or stdin
+    format: EntryFormat,
+    /// Files that couldn't be read and were skipped, with the reason
+    skipped: Mutex<Vec<(String, Error)>>,
+    /// Ids of entries dropped for repeating an earlier id with different content, with the file
+    /// each was read from
+    collisions: Mutex<Vec<(String, String)>>,
+    /// Ids of payments left unapplied because the invoice they reference isn't an outstanding
+    /// invoice of their party dated on or before them, with the id referenced
+    unmatched: Mutex<Vec<(String, String)>>,
 }
 
 type Balances = HashMap<JournalAccount, JournalAmount>;
 
+/// Revenue from sale invoices net of sales tax and the tax collected on top of it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Revenue {
+    pub net: Money,
+    pub tax: Money,
+}
+
+impl Revenue {
+    /// Revenue including the sales tax collected
+    pub fn gross(&self) -> Money {
+        self.net + self.tax
+    }
+}
+
+/// Change in cash over a period by the activity that caused it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CashFlowStatement {
+    pub operating: Money,
+    pub investing: Money,
+    pub financing: Money,
+}
+
+impl CashFlowStatement {
+    /// Net change in cash across every section
+    pub fn net_change(&self) -> Money {
+        self.operating + self.investing + self.financing
+    }
+}
+
+/// An invoice occurrence and the amount still owed on it after payments are applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvoiceBalance {
+    pub id: String,
+    pub date: NaiveDate,
+    pub party: String,
+    pub amount: Money,
+}
+
+impl InvoiceBalance {
+    /// Balance of an invoice occurrence less the payments attached to it, or none if they settle it
+    fn unpaid(id: String, date: NaiveDate, invoice: Invoice) -> Result<Option<InvoiceBalance>> {
+        let paid = Money::sum(invoice.payments.iter().map(|payment| payment.amount))?;
+        let amount = invoice.total()? - paid;
+        if !invoice.payments.is_empty() && amount == Money::zero() {
+            return Ok(None);
+        }
+        Ok(Some(InvoiceBalance {
+            id,
+            date,
+            party: invoice.party,
+            amount,
+        }))
+    }
+
+    /// Apply a payment to the invoice it references or otherwise to the party's oldest outstanding
+    /// invoices first. A referenced invoice must be the payment's party's and dated on or before
+    /// the payment. Any amount in excess of what is outstanding, or a payment referencing no such
+    /// outstanding invoice, is left unapplied as credit. Returns false for a payment referencing
+    /// no such invoice so it can be reported.
+    fn allocate(invoices: &mut [InvoiceBalance], date: NaiveDate, payment: &Payment) -> bool {
+        let mut remaining = payment.amount;
+        let outstanding = invoices
+            .iter_mut()
+            .filter(|invoice| invoice.party == payment.party && invoice.amount > Money::zero());
+        let outstanding: Vec<&mut InvoiceBalance> = match &payment.invoice {
+            Some(id) => outstanding
+                .filter(|invoice| invoice.id == *id && invoice.date <= date)
+                .take(1)
+                .collect(),
+            None => outstanding.collect(),
+        };
+        if payment.invoice.is_some() && outstanding.is_empty() {
+            return false;
+        }
+        for invoice in outstanding {
+            if remaining <= Money::zero() {
+                break;
+            }
+            let applied = if remaining < invoice.amount {
+                remaining
+            } else {
+                invoice.amount
+            };
+            invoice.amount -= applied;
+            remaining -= applied;
+        }
+        true
+    }
+}
+
 impl Ledger {
     pub fn new(dir: Option<&str>) -> Self {
         Ledger {
             dir: dir.map(ToOwned::to_owned),
+            dedup: false,
+            reference: None,
+            no_recurring: false,
+            fiscal_year_start: (1, 1),
+            date_order: None,
+            format: EntryFormat::Yaml,
+            skipped: Mutex::new(Vec::new()),
+            collisions: Mutex::new(Vec::new()),
+            unmatched: Mutex::new(Vec::new()),
         }
     }
 
-    /// Reads an entire dir of files by line
-    fn dir_lines(dir: String) -> impl Stream<Item = std::io::Result<String>> {
+    /// Drop repeated entries with the same id, or the same content read from another file
+    /// when they have no id, such as the same file included twice. A repeated id whose content
+    /// differs from the first is also dropped but recorded so the collision can be reported.
+    pub fn dedup(mut self) -> Self {
+        self.dedup = true;
+        self
+    }
+
+    /// Only read entries with the given reference number, such as an invoice number
+    pub fn with_reference(mut self, reference: &str) -> Self {
+        self.reference = Some(reference.to_owned());
+        self
+    }
+
+    /// Treat recurring entries as a single occurrence on their first date
+    pub fn no_recurring(mut self) -> Self {
+        self.no_recurring = true;
+        self
+    }
+
+    /// Read entry dates not written as YYYY-MM-DD with the month and day in `date_order`,
+    /// rather than rejecting those that could be read either way
+    pub fn with_date_order(mut self, date_order: DateOrder) -> Self {
+        self.date_order = Some(date_order);
+        self
+    }
+
+    /// Read entries in `format` rather than as YAML, such as a CSV file or CSV piped to stdin
+    pub fn with_format(mut self, format: EntryFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Parse a date given other than in an entry, such as on the command line, the same way as
+    /// entry dates
+    pub fn parse_date(&self, field: &str, value: &str) -> Result<NaiveDate> {
+        entry::parse_date(field, value, self.date_order)
+    }
+
+    /// Start fiscal years on the given `month` and `day` rather than January 1st
+    pub fn with_fiscal_year_start(mut self, month: u32, day: u32) -> Result<Self> {
+        // checked against a non-leap year so every year has the start date
+        NaiveDate::from_ymd_opt(2001, month, day)
+            .with_context(|| format!("Invalid fiscal year start month {} day {}", month, day))?;
+        self.fiscal_year_start = (month, day);
+        Ok(self)
+    }
+
+    /// The fiscal year `date` falls in, named by the calendar year it ends in
+    pub fn fiscal_year(&self, date: NaiveDate) -> i32 {
+        let (month, day) = self.fiscal_year_start;
+        let start = NaiveDate::from_ymd(date.year(), month, day);
+        let next_start = if date >= start {
+            NaiveDate::from_ymd(date.year() + 1, month, day)
+        } else {
+            start
+        };
+        next_start.pred().year()
+    }
+
+    /// First and last dates of fiscal `year`
+    pub fn fiscal_year_bounds(&self, year: i32) -> (NaiveDate, NaiveDate) {
+        let (month, day) = self.fiscal_year_start;
+        let start = NaiveDate::from_ymd(year, month, day);
+        let start = if self.fiscal_year(start) == year {
+            start
+        } else {
+            NaiveDate::from_ymd(year - 1, month, day)
+        };
+        (
+            start,
+            NaiveDate::from_ymd(start.year() + 1, month, day).pred(),
+        )
+    }
+
+    /// Takes the list of files skipped so far because they couldn't be read (permissions, not
+    /// UTF-8, etc) along with the reason for each
+    pub fn skipped_files(&self) -> Vec<(String, Error)> {
+        self.skipped
+            .lock()
+            .map(|mut skipped| std::mem::take(&mut *skipped))
+            .unwrap_or_default()
+    }
+
+    /// Takes the ids of entries dropped so far when deduplicating because an earlier entry had
+    /// the same id but different content, along with the file each was read from
+    pub fn id_collisions(&self) -> Vec<(String, String)> {
+        self.collisions
+            .lock()
+            .map(|mut collisions| std::mem::take(&mut *collisions))
+            .unwrap_or_default()
+    }
+
+    /// Takes the ids of payments left unapplied so far by `invoice_balances` because the invoice
+    /// they reference isn't an outstanding invoice of their party dated on or before them, along
+    /// with the invoice id referenced
+    pub fn unmatched_payments(&self) -> Vec<(String, String)> {
+        self.unmatched
+            .lock()
+            .map(|mut unmatched| std::mem::take(&mut *unmatched))
+            .unwrap_or_default()
+    }
+
+    /// Reads an entire dir of files by line.
+    /// Each file is read whole so that one that can't be read is skipped and recorded rather than
+    /// ending the stream or leaving a partial document behind.
+    fn dir_lines(&self, dir: String) -> impl Stream<Item = std::io::Result<String>> + '_ {
+        self.dir_files(dir)
+            .map_ok(|(_, content)| {
+                stream::iter(
+                    content
+                        .lines()
+                        .map(|line| Ok(line.to_owned()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .try_flatten()
+    }
+
+    /// Path and content of each file in a dir in path order, skipping and recording any that
+    /// can't be read
+    fn dir_files(&self, dir: String) -> impl Stream<Item = std::io::Result<(String, String)>> + '_ {
+        stream::once(async move {
+            let mut paths: Vec<_> = WalkDir::new(dir)
+                .try_filter_map(|dir_entry: DirEntry| async move {
+                    let path = dir_entry.path();
+                    let filestem = path
+                        .file_stem()
+                        .ok_or_else(|| std::io::Error::new(ErrorKind::Other, "No file stem"))?
+                        .to_string_lossy();
+                    Ok((!path.is_dir() && !filestem.starts_with('.')).then_some(path))
+                })
+                .try_collect()
+                .await?;
+            paths.sort();
+            Ok::<_, std::io::Error>(stream::iter(paths).map(Ok))
+        })
+        .try_flatten()
+        .try_filter_map(move |path| async move {
+            let file = path.to_string_lossy().into_owned();
+            match fs::read_to_string(&path).await {
+                Ok(content) => Ok(Some((file, content))),
+                Err(err) => {
+                    if let Ok(mut skipped) = self.skipped.lock() {
+                        skipped.push((file, Error::new(err)));
+                    }
+                    Ok(None)
+                }
+            }
+        })
+    }
+
+    /// Latest modification time of own dir or anything in it, used to watch for changes.
+    /// Directories are included so that added or removed files are noticed.
+    pub async fn last_modified(&self) -> Result<Option<SystemTime>> {
+        let dir = self.dir.clone().context("Can't watch entries from stdin")?;
+        let modified = fs::metadata(&dir).await?.modified()?;
+        WalkDir::new(dir)
+            .map_err(Error::new)
+            .try_fold(Some(modified), |latest, dir_entry| async move {
+                let modified = fs::metadata(dir_entry.path()).await?.modified()?;
+                Ok(latest.max(Some(modified)))
+            })
+            .await
+    }
+
+    /// Each file in own dir along with its entries written out in normalised form
+    pub async fn formatted_files(&self) -> Result<Vec<(String, String)>> {
+        let dir = self
+            .dir
+            .clone()
+            .context("Can't format entries from stdin")?;
         WalkDir::new(dir)
+            .map_err(Error::new)
             .try_filter_map(|dir_entry: DirEntry| async move {
                 let path = dir_entry.path();
-                let filestem = path
-                    .file_stem()
-                    .ok_or_else(|| std::io::Error::new(ErrorKind::Other, "No file stem"))?
-                    .to_string_lossy();
-                if path.is_dir() || filestem.starts_with('.') {
+                let hidden = path
+                    .file_name()
+                    .map_or(true, |name| name.to_string_lossy().starts_with('.'));
+                if path.is_dir() || hidden {
                     return Ok(None);
-                };
-                File::open(&path).await.map(Option::Some)
+                }
+                let content = fs::read_to_string(&path).await?;
+                let formatted = entry::format_docs(&content)
+                    .with_context(|| format!("Failed to format {}", path.display()))?;
+                Ok(Some((path.to_string_lossy().into_owned(), formatted)))
             })
-            .map_ok(|file| BufReader::new(file).lines())
-            .try_flatten()
+            .try_collect()
+            .await
     }
 
     /// Reads lines of self.dir or stdin if None
     fn lines(&self) -> impl Stream<Item = std::io::Result<String>> + '_ {
         if let Some(dir) = self.dir.clone() {
-            Self::dir_lines(dir).left_stream()
+            self.dir_lines(dir).left_stream()
         } else {
             BufReader::new(stdin()).lines().right_stream()
         }
     }
 
-    /// Parse own stream of lines into `Entry`s
-    pub fn entries(&self) -> impl Stream<Item = Result<Entry>> + '_ {
-        self.lines()
+    /// Parse a stream of lines into `Entry`s
+    fn entries_from_lines(
+        lines: impl Stream<Item = std::io::Result<String>>,
+        date_order: Option<DateOrder>,
+    ) -> impl Stream<Item = Result<Entry>> {
+        lines
             .chunk_by_line("---")
             .map_err(Error::new) // map to anyhow::Error from here on
-            .and_then(|doc| future::ready(doc.parse()))
+            .and_then(move |doc| future::ready(Entry::parse(&doc, date_order)))
+    }
+
+    /// Parse `Entry`s from any buffered reader, such as a socket or pipe, rather than a dir
+    pub fn entries_from_reader(reader: impl BufRead + Unpin) -> impl Stream<Item = Result<Entry>> {
+        Self::entries_from_lines(reader.lines(), None)
+    }
+
+    /// Parse `Entry`s from CSV with a header row, one entry per row as each is read, with dates
+    /// not written as YYYY-MM-DD in `date_order`
+    pub fn entries_from_csv(
+        reader: impl std::io::Read,
+        date_order: Option<DateOrder>,
+    ) -> impl Stream<Item = Result<Entry>> {
+        stream::iter(Entry::from_csv(reader, date_order))
+    }
+
+    /// Parse own CSV file, or stdin, paired with the path read from, or `-` for stdin
+    fn csv_entries(&self) -> BoxStream<'static, Result<(String, Entry)>> {
+        let date_order = self.date_order;
+        match self.dir.clone() {
+            Some(file) => stream::once(future::ready(
+                std::fs::File::open(&file).with_context(|| format!("Failed to read {}", file)),
+            ))
+            .map_ok(move |reader| {
+                let file = file.clone();
+                Self::entries_from_csv(reader, date_order)
+                    .map_ok(move |entry| (file.clone(), entry))
+            })
+            .try_flatten()
+            .boxed(),
+            None => Self::entries_from_csv(std::io::stdin(), date_order)
+                .map_ok(|entry| ("-".to_string(), entry))
+                .boxed(),
+        }
+    }
+
+    /// Every entry that fails to parse, paired with the path of the file it's in, or `-` for
+    /// stdin. Failures are skipped rather than ending the stream so all of them can be fixed
+    /// together.
+    pub fn parse_errors(&self) -> BoxStream<'_, (String, Error)> {
+        fn errors(
+            source: String,
+            entries: impl Stream<Item = Result<Entry>>,
+        ) -> impl Stream<Item = (String, Error)> {
+            entries.filter_map(move |entry| {
+                future::ready(entry.err().map(|err| (source.clone(), err)))
+            })
+        }
+        if self.format == EntryFormat::Csv {
+            let source = self.dir.clone().unwrap_or_else(|| "-".to_string());
+            return errors(source, self.csv_entries().map_ok(|(_, entry)| entry)).boxed();
+        }
+        match self.dir.clone() {
+            Some(dir) => {
+                let date_order = self.date_order;
+                self.dir_files(dir.clone())
+                    .map(move |file| match file {
+                        Ok((file, content)) => {
+                            let lines = content
+                                .lines()
+                                .map(|line| Ok(line.to_owned()))
+                                .collect::<Vec<_>>();
+                            errors(
+                                file,
+                                Self::entries_from_lines(stream::iter(lines), date_order),
+                            )
+                            .boxed()
+                        }
+                        Err(err) => {
+                            stream::once(future::ready((dir.clone(), Error::new(err)))).boxed()
+                        }
+                    })
+                    .flatten()
+                    .boxed()
+            }
+            None => errors(
+                "-".to_string(),
+                Self::entries_from_lines(self.lines(), self.date_order),
+            )
+            .boxed(),
+        }
+    }
+
+    /// Parse own stream of lines into `Entry`s, or own CSV rows when the format is CSV
+    pub fn entries(&self) -> impl Stream<Item = Result<Entry>> + '_ {
+        let mut seen = HashMap::new();
+        self.entries_with_source()
+            .try_filter(move |(source, entry)| {
+                future::ready(
+                    self.reference.as_ref().map_or(true, |reference| {
+                        entry.reference().as_ref() == Some(reference)
+                    }) && (!self.dedup || self.first_seen(&mut seen, source, entry)),
+                )
+            })
+            .map_ok(move |(_, entry)| {
+                if self.no_recurring {
+                    entry.first_occurrence()
+                } else {
+                    entry
+                }
+            })
+    }
+
+    /// Like `entries` but paired with the path of the file each came from, or `-` for stdin,
+    /// and without filtering by reference or dropping duplicates
+    pub fn entries_with_source(&self) -> BoxStream<'_, Result<(String, Entry)>> {
+        if self.format == EntryFormat::Csv {
+            return self.csv_entries();
+        }
+        match self.dir.clone() {
+            Some(dir) => self
+                .dir_files(dir)
+                .map_err(Error::new)
+                .map_ok(move |(file, content)| {
+                    let lines = content
+                        .lines()
+                        .map(|line| Ok(line.to_owned()))
+                        .collect::<Vec<_>>();
+                    Self::entries_from_lines(stream::iter(lines), self.date_order)
+                        .map_ok(move |entry| (file.clone(), entry))
+                })
+                .try_flatten()
+                .boxed(),
+            None => Self::entries_from_lines(self.lines(), self.date_order)
+                .map_ok(|entry| ("-".to_string(), entry))
+                .boxed(),
+        }
+    }
+
+    /// Whether `entry` is the first with its id, recording it in `seen`. Entries without an id
+    /// are told apart by content and only taken as repeats when read from another file, so
+    /// identical entries in the same file all count.
+    fn first_seen(
+        &self,
+        seen: &mut HashMap<String, (String, u64)>,
+        source: &str,
+        entry: &Entry,
+    ) -> bool {
+        let key = if entry.has_auto_id() {
+            format!("{:x}", entry.content_hash())
+        } else {
+            entry.id()
+        };
+        match seen.get(&key) {
+            None => {
+                seen.insert(key, (source.to_owned(), entry.content_hash()));
+                true
+            }
+            Some((first_source, _)) if entry.has_auto_id() => first_source == source,
+            Some((_, hash)) => {
+                if *hash != entry.content_hash() {
+                    if let Ok(mut collisions) = self.collisions.lock() {
+                        collisions.push((entry.id(), source.to_owned()));
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    /// Own stream of `Entry`s touching any of `accounts`, or all entries if `accounts` is empty.
+    /// Each of `accounts` may be a glob such as `Bank*` or a regex prefixed with `re:`.
+    pub fn entries_filtered(
+        &self,
+        accounts: Vec<String>,
+    ) -> impl Stream<Item = Result<Entry>> + '_ {
+        stream::once(future::ready(Self::account_patterns(&accounts)))
+            .map_ok(move |patterns| {
+                self.entries().try_filter_map(move |entry| {
+                    future::ready(
+                        Self::touches_any(&entry, &patterns)
+                            .map(|touches| touches.then_some(entry)),
+                    )
+                })
+            })
+            .try_flatten()
+    }
+
+    /// Parse each of `accounts` as an exact name, glob or `re:` regex
+    fn account_patterns(accounts: &[String]) -> Result<Vec<AccountPattern>> {
+        accounts.iter().map(|account| account.parse()).collect()
+    }
+
+    /// Whether `entry` touches an account matching any of `patterns`, or true if there are none
+    fn touches_any(entry: &Entry, patterns: &[AccountPattern]) -> Result<bool> {
+        if patterns.is_empty() {
+            return Ok(true);
+        }
+        Ok(entry
+            .accounts()?
+            .iter()
+            .any(|account| patterns.iter().any(|pattern| pattern.matches(account))))
+    }
+
+    /// Like `journal_filtered` but in date order, streamed without collecting the whole journal
+    /// for ledgers whose entries are in date order by file name and within each file. Lines are
+    /// held back only until an entry dated after them is read, so later occurrences of recurring
+    /// entries fall into place. Errors if an entry is dated before lines already streamed.
+    pub fn journal_sorted(
+        &self,
+        accounts: Vec<String>,
+        party: Option<String>,
+        until: Option<NaiveDate>,
+    ) -> impl Stream<Item = Result<JournalEntry>> + '_ {
+        let today = Local::today().naive_local();
+        let mut pending: BTreeMap<(NaiveDate, usize), JournalEntry> = BTreeMap::new();
+        let mut count = 0;
+        let mut streamed: Option<NaiveDate> = None;
+        self.entries_filtered(accounts)
+            .map_ok(Some)
+            .chain(stream::once(future::ready(Ok(None))))
+            .map(move |entry| {
+                // lines dated before the next entry's first date are ready, all are at the end
+                let ready = match entry? {
+                    Some(entry) => {
+                        let first = entry.dates(until.unwrap_or(today)).next();
+                        if let (Some(first), Some(streamed)) = (first, streamed) {
+                            if first < streamed {
+                                bail!(
+                                    "Entry {} dated {} is before journal lines dated {}, \
+                                    entries must be in date order",
+                                    entry.id(),
+                                    first,
+                                    streamed
+                                );
+                            }
+                        }
+                        for line in JournalEntry::from_entry(entry, until)? {
+                            pending.insert((line.0, count), line);
+                            count += 1;
+                        }
+                        match first {
+                            Some(first) => {
+                                let later = pending.split_off(&(first, 0));
+                                std::mem::replace(&mut pending, later)
+                            }
+                            None => BTreeMap::new(),
+                        }
+                    }
+                    None => std::mem::take(&mut pending),
+                };
+                if let Some(((date, _), _)) = ready.iter().next_back() {
+                    streamed = Some(*date);
+                }
+                Ok(stream::iter(ready.into_values().map(Ok)))
+            })
+            .try_flatten()
+            .try_filter(move |line| {
+                future::ready(party.as_ref().map_or(true, |p| line.3.as_ref() == Some(p)))
+            })
     }
 
     /// Convert own stream of `Entry`s into `JournalEntry`s
     pub fn journal(&self, party: Option<String>) -> impl Stream<Item = Result<JournalEntry>> + '_ {
-        self.entries()
-            .and_then(|entry| async {
-                Ok(stream::iter(JournalEntry::from_entry(entry, None)?).map(Ok))
+        self.journal_filtered(Vec::new(), party, None)
+    }
+
+    /// Convert own stream of `Entry`s touching any of `accounts` into `JournalEntry`s
+    /// dated up to `until` (defaults to today).
+    /// All lines of a matching entry are kept so its contra accounts are still shown.
+    pub fn journal_filtered(
+        &self,
+        accounts: Vec<String>,
+        party: Option<String>,
+        until: Option<NaiveDate>,
+    ) -> impl Stream<Item = Result<JournalEntry>> + '_ {
+        self.entries_filtered(accounts)
+            .and_then(move |entry| async move {
+                Ok(stream::iter(JournalEntry::from_entry(entry, until)?).map(Ok))
             })
             .try_flatten()
             .try_filter(move |entry| {
-                future::ready(
-                    party
-                        .clone()
-                        .map_or(true, |p| entry.3.clone().map_or(false, |party| p == party)),
-                )
+                future::ready(party.as_ref().map_or(true, |p| entry.3.as_ref() == Some(p)))
+            })
+    }
+
+    /// Like `journal_filtered` but keeping the lines of each entry occurrence together.
+    /// An entry is kept for `party` if any of its lines are.
+    pub fn journal_grouped(
+        &self,
+        accounts: Vec<String>,
+        party: Option<String>,
+        until: Option<NaiveDate>,
+    ) -> impl Stream<Item = Result<JournalGroup>> + '_ {
+        self.entries_filtered(accounts)
+            .and_then(move |entry| async move {
+                Ok(stream::iter(JournalGroup::from_entry(&entry, until)?).map(Ok))
+            })
+            .try_flatten()
+            .try_filter(move |group| {
+                future::ready(party.as_ref().map_or(true, |p| {
+                    group.lines.iter().any(|line| line.3.as_ref() == Some(p))
+                }))
             })
     }
 
-    /// Get balances for each account appearing in own stream of `JournalEntry`s
+    /// Get balances for each account appearing in own stream of `JournalEntry`s.
+    /// Journal entries are folded as they stream in so memory is bounded by the number of distinct
+    /// accounts (and the largest single entry file) rather than the size of the whole ledger.
+    /// Each line's account name is moved into the map, so nothing is cloned per line.
     pub fn balances(&self, party: Option<String>) -> impl Future<Output = Result<Balances>> + '_ {
-        self.journal(party).try_fold(
-            HashMap::new(),
-            |mut acc, JournalEntry(_, account, amount, _)| async move {
-                acc.entry(account.clone())
-                    .and_modify(|total: &mut JournalAmount| {
-                        total.add_assign(amount);
-                    })
-                    .or_insert(amount);
-                Ok(acc)
-            },
+        self.balances_as_of(party, None)
+    }
+
+    /// Get balances for each account as of `until` (defaults to today)
+    pub fn balances_as_of(
+        &self,
+        party: Option<String>,
+        until: Option<NaiveDate>,
+    ) -> impl Future<Output = Result<Balances>> + '_ {
+        self.journal_filtered(Vec::new(), party, until)
+            .try_fold(HashMap::new(), |acc, line| {
+                future::ready(Self::add_line(acc, line))
+            })
+    }
+
+    /// Add a journal line to the balance of its account
+    fn add_line(
+        mut acc: Balances,
+        JournalEntry(_, account, amount, _, _): JournalEntry,
+    ) -> Result<Balances> {
+        // checked so a pathological amount is an error rather than a panic
+        if let Some(total) = acc.get_mut(&account) {
+            total
+                .try_add_assign(amount)
+                .with_context(|| format!("Failed to total balance of {}", account))?;
+        } else {
+            acc.insert(account, amount);
+        }
+        Ok(acc)
+    }
+
+    /// Get balances of only the given `accounts`, or of every account if `accounts` is empty
+    pub async fn balances_filtered(
+        &self,
+        accounts: Vec<String>,
+        party: Option<String>,
+    ) -> Result<Balances> {
+        self.balances_between(None, None, accounts, party).await
+    }
+
+    /// Get the journal lines posted to each of `accounts` in date order, for a party if given
+    pub async fn general_ledger(
+        &self,
+        accounts: Vec<String>,
+        party: Option<String>,
+    ) -> Result<BTreeMap<JournalAccount, Vec<JournalEntry>>> {
+        let patterns = Self::account_patterns(&accounts)?;
+        let mut ledger: BTreeMap<_, Vec<_>> = self
+            .journal_filtered(accounts, party, None)
+            .try_filter(|line| {
+                future::ready(patterns.iter().any(|pattern| pattern.matches(&line.1)))
+            })
+            .try_fold(BTreeMap::new(), |mut ledger, line| async move {
+                ledger
+                    .entry(line.1.clone())
+                    .or_insert_with(Vec::new)
+                    .push(line);
+                Ok(ledger)
+            })
+            .await?;
+        for lines in ledger.values_mut() {
+            lines.sort_by_key(|line| line.0);
+        }
+        Ok(ledger)
+    }
+
+    /// Find accounts in `chart` with `tag` whose running balance goes below zero on their normal
+    /// side at any point, processing journal lines in date order. Each is returned with the
+    /// first date it went negative, even if it later recovers.
+    pub async fn negative_dips(
+        &self,
+        chart: &ChartOfAccounts,
+        tag: &Tag,
+    ) -> Result<BTreeMap<JournalAccount, NaiveDate>> {
+        let accounts: Vec<String> = chart
+            .accounts()
+            .iter()
+            .filter(|account| account.has_tag(tag))
+            .map(|account| account.name.clone())
+            .collect();
+        let mut dips = BTreeMap::new();
+        for (name, lines) in self.general_ledger(accounts, None).await? {
+            let account = chart.get(&name)?;
+            let mut running = Money::zero();
+            for JournalEntry(date, _, amount, _, _) in lines {
+                let change = account.natural_balance(&amount);
+                running = match running.checked_sub_nonneg(-change) {
+                    Some(running) => running,
+                    None => {
+                        dips.entry(name.clone()).or_insert(date);
+                        running.try_add(change)?
+                    }
+                };
+            }
+        }
+        Ok(dips)
+    }
+
+    /// Close the books as of `as_of` into a single general journal entry on that date carrying
+    /// the balance of every account, so entries up to then can be replaced by it. Payables and
+    /// receivables are carried by party. Anything the balances are out by is taken up by
+    /// `equity_account`.
+    pub async fn snapshot_opening(&self, as_of: NaiveDate, equity_account: &str) -> Result<Entry> {
+        let (balances, parties) = future::try_join(
+            self.balances_as_of(None, Some(as_of)),
+            self.party_balances(Some(as_of)),
         )
+        .await?;
+        let mut parties: Vec<(String, (JournalAmount, JournalAmount))> =
+            parties.into_iter().collect();
+        parties.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut accounts: Vec<(String, JournalAmount)> = balances.into_iter().collect();
+        accounts.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut lines = Vec::new();
+        for (account, balance) in accounts {
+            let mut unassigned = balance.to_signed_money();
+            for (party, (payable, receivable)) in parties.iter() {
+                let amount = match account.as_str() {
+                    "Accounts Payable" => payable.to_signed_money(),
+                    "Accounts Receivable" => receivable.to_signed_money(),
+                    _ => continue,
+                };
+                if !amount.is_zero() {
+                    unassigned = unassigned.try_add(-amount)?;
+                    lines.push(GeneralLine {
+                        account: account.clone(),
+                        amount,
+                        party: Some(party.clone()),
+                    });
+                }
+            }
+            if !unassigned.is_zero() {
+                lines.push(GeneralLine {
+                    account,
+                    amount: unassigned,
+                    party: None,
+                });
+            }
+        }
+        let residual = Money::sum(lines.iter().map(|line| line.amount))?;
+        if !residual.is_zero() {
+            lines.push(GeneralLine {
+                account: equity_account.to_string(),
+                amount: -residual,
+                party: None,
+            });
+        }
+        Entry::from_lines(
+            as_of,
+            Some(format!("Opening balances as of {}", as_of)),
+            &lines,
+        )
+    }
+
+    /// Whether the balances of all accounts as of today net to zero
+    pub async fn is_balanced(&self) -> Result<bool> {
+        Ok(Self::assert_balanced(&self.balances(None).await?).is_ok())
+    }
+
+    /// Account with the largest balance in `balances` by absolute amount, ties going to the
+    /// first account by name
+    pub fn largest(balances: &Balances) -> Option<(&JournalAccount, &JournalAmount)> {
+        balances.iter().max_by(|(a_name, a), (b_name, b)| {
+            a.money()
+                .abs()
+                .cmp(&b.money().abs())
+                .then_with(|| b_name.cmp(a_name))
+        })
+    }
+
+    /// Error with the amount `balances` are out of balance by unless they net to zero
+    pub fn assert_balanced(balances: &Balances) -> Result<()> {
+        let total = JournalAmount::sum(balances.values().copied())?;
+        match total {
+            total if total.money().is_zero() => Ok(()),
+            JournalAmount::Debit(money) => {
+                bail!("Ledger is out of balance by a debit of {}", money)
+            }
+            JournalAmount::Credit(money) => {
+                bail!("Ledger is out of balance by a credit of {}", money)
+            }
+        }
+    }
+
+    /// Get the difference in balances of each account between own and other's entries (self - other).
+    /// Accounts whose balances are equal are omitted.
+    pub async fn diff_balances(
+        &self,
+        other: &Ledger,
+    ) -> Result<BTreeMap<JournalAccount, JournalAmount>> {
+        let (balances, other_balances) =
+            future::try_join(self.balances(None), other.balances(None)).await?;
+        let mut diff: BTreeMap<_, _> = balances.into_iter().collect();
+        for (account, amount) in other_balances {
+            diff.entry(account)
+                .and_modify(|total: &mut JournalAmount| *total = *total - amount)
+                .or_insert(-amount);
+        }
+        diff.retain(|_, amount| *amount != JournalAmount::default());
+        Ok(diff)
+    }
+
+    /// Export own journal as CSV in the layout of GnuCash's transaction import, one row per
+    /// journal line. Lines of the same entry occurrence share a transaction id so they import as
+    /// splits of one transaction. Debits are deposits and credits are withdrawals.
+    pub async fn to_gnucash_csv(&self) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record([
+            "Date",
+            "Transaction ID",
+            "Description",
+            "Account",
+            "Deposit",
+            "Withdrawal",
+        ])?;
+        let writer = self
+            .entries()
+            .try_fold(writer, |mut writer, entry| async move {
+                let id = entry.id();
+                for JournalEntry(date, account, amount, party, _) in
+                    JournalEntry::from_entry(entry, None)?
+                {
+                    let (deposit, withdrawal) = match amount {
+                        JournalAmount::Debit(money) => (money.0.to_string(), String::new()),
+                        JournalAmount::Credit(money) => (String::new(), money.0.to_string()),
+                    };
+                    writer.write_record([
+                        date.to_string(),
+                        format!("{}|{}", id, date),
+                        party.unwrap_or_default(),
+                        account,
+                        deposit,
+                        withdrawal,
+                    ])?;
+                }
+                Ok(writer)
+            })
+            .await?;
+        let csv = writer.into_inner().map_err(|err| err.into_error())?;
+        Ok(String::from_utf8(csv)?)
+    }
+
+    /// Build a starter chart of accounts listing every account used in own entries.
+    /// Types are guessed from well known names or else from how the account is first used.
+    pub async fn init_chart(&self) -> Result<ChartOfAccounts> {
+        let used = self
+            .entries()
+            .try_fold(BTreeMap::new(), |mut used, entry| async move {
+                let mut accounts = Vec::new();
+                match entry.body() {
+                    EntryBody::PurchaseInvoice(invoice) => {
+                        accounts.extend(
+                            invoice
+                                .items
+                                .into_iter()
+                                .map(|i| (i.account, Type::Expense)),
+                        );
+                        accounts.extend(
+                            invoice
+                                .extras
+                                .into_iter()
+                                .flatten()
+                                .map(|e| (e.account, Type::Expense)),
+                        );
+                        accounts.extend(
+                            invoice
+                                .payments
+                                .into_iter()
+                                .map(|p| (p.account, Type::Asset)),
+                        );
+                        accounts.push(("Accounts Payable".to_owned(), Type::Liability));
+                    }
+                    EntryBody::SaleInvoice(invoice) => {
+                        accounts.extend(
+                            invoice
+                                .items
+                                .into_iter()
+                                .map(|i| (i.account, Type::Revenue)),
+                        );
+                        accounts.extend(
+                            invoice
+                                .extras
+                                .into_iter()
+                                .flatten()
+                                .map(|e| (e.account, Type::Liability)),
+                        );
+                        accounts.extend(
+                            invoice
+                                .payments
+                                .into_iter()
+                                .map(|p| (p.account, Type::Asset)),
+                        );
+                        accounts.push(("Accounts Receivable".to_owned(), Type::Asset));
+                    }
+                    EntryBody::PaymentSent(payment) => {
+                        accounts.extend(
+                            payment
+                                .accounts
+                                .into_iter()
+                                .map(|p| (p.account, Type::Asset)),
+                        );
+                        accounts.push(("Accounts Payable".to_owned(), Type::Liability));
+                    }
+                    EntryBody::PaymentReceived(payment) => {
+                        accounts.extend(
+                            payment
+                                .accounts
+                                .into_iter()
+                                .map(|p| (p.account, Type::Asset)),
+                        );
+                        accounts.push(("Accounts Receivable".to_owned(), Type::Asset));
+                    }
+                    EntryBody::Split(split) => {
+                        accounts.push((split.from, Type::Expense));
+                        accounts.extend(split.to.into_iter().map(|(a, _)| (a, Type::Expense)));
+                    }
+                    EntryBody::GeneralJournal(general) => {
+                        accounts.extend(general.lines.into_iter().map(|line| {
+                            let acc_type = if line.amount.is_negative() {
+                                Type::Liability
+                            } else {
+                                Type::Asset
+                            };
+                            (line.account, acc_type)
+                        }));
+                    }
+                }
+                for (account, acc_type) in accounts {
+                    used.entry(account).or_insert(acc_type);
+                }
+                Ok(used)
+            })
+            .await?;
+        Ok(ChartOfAccounts::new(
+            used.into_iter()
+                .map(|(name, acc_type)| {
+                    Account::new(Type::guess(&name).unwrap_or(acc_type), &name, Vec::new())
+                })
+                .collect(),
+        ))
     }
 
     /// Run report to get total breakdowns of own balances based on give `ChartOfAccounts` and report spec
@@ -106,29 +1016,319 @@ impl Ledger {
         &'a self,
         chart: &ChartOfAccounts,
         report: &'a mut ReportNode,
+        as_of: Option<NaiveDate>,
     ) -> Result<&'a mut ReportNode> {
-        self.balances(None)
+        let balances = self.balances_as_of(None, as_of).await?;
+        Self::apply_balances(chart, report, balances)?;
+        Ok(report)
+    }
+
+    /// Apply `balances` to the report nodes their accounts match and check the report reconciles
+    fn apply_balances(
+        chart: &ChartOfAccounts,
+        report: &mut ReportNode,
+        balances: Balances,
+    ) -> Result<()> {
+        let balances = balances
+            .into_iter()
+            .map(|(account, balance)| Ok((chart.get(&account)?, balance)))
+            .collect::<Result<Vec<_>>>()?;
+        for (account, balance) in balances.iter() {
+            // recursively find total in report to which account applies and add name to list and value to total
+            report.apply_balance((account, balance))?;
+        }
+        report.reconcile(
+            balances
+                .iter()
+                .map(|(account, balance)| (*account, balance)),
+        )
+    }
+
+    /// Get balances of lines dated from `from` up to `until` (defaults to today), of only the
+    /// given `accounts` or of every account if `accounts` is empty, for a party if given.
+    /// Recurring entries count each of their occurrences within the range.
+    pub async fn balances_between(
+        &self,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+        accounts: Vec<String>,
+        party: Option<String>,
+    ) -> Result<Balances> {
+        let mut balances = self
+            .journal_filtered(accounts.clone(), party, until)
+            .try_filter(|line| future::ready(from.map_or(true, |from| line.0 >= from)))
+            .try_fold(HashMap::new(), |acc, line| {
+                future::ready(Self::add_line(acc, line))
+            })
+            .await?;
+        // contra lines of entries touching `accounts` are kept by `journal_filtered`
+        let patterns = Self::account_patterns(&accounts)?;
+        if !patterns.is_empty() {
+            balances.retain(|account, _| patterns.iter().any(|pattern| pattern.matches(account)));
+        }
+        Ok(balances)
+    }
+
+    /// Revenue less expenses from `from` up to `until`, by the account types in `chart`
+    pub async fn net_income(
+        &self,
+        chart: &ChartOfAccounts,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<Money> {
+        self.balances_between(from, until, Vec::new(), None)
             .await?
             .iter()
-            .try_fold(report, |report, (account, balance)| {
-                // recursively find total in report to which account applies and add name to list and value to total
+            .try_fold(Money::zero(), |mut net, (account, balance)| {
                 let account = chart.get(account)?;
-                report.apply_balance((account, balance))?;
-                Ok(report)
+                if matches!(account.acc_type, Type::Revenue | Type::Expense) {
+                    // credits to revenue add to income and debits to expenses take from it
+                    net.try_add_assign(-balance.to_signed_money())?;
+                }
+                Ok(net)
             })
     }
 
-    pub fn payable(&self) -> impl Future<Output = Result<HashMap<String, JournalAmount>>> + '_ {
-        self.journal(None).try_fold(
+    /// Standard income statement of revenue and expenses from `from` up to `until`, grouped by
+    /// account type, with net income as its total
+    pub async fn income_statement(
+        &self,
+        chart: &ChartOfAccounts,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+    ) -> Result<ReportNode> {
+        let mut report = ReportNode {
+            header: "Net Income".to_string(),
+            types: vec![Type::Revenue, Type::Expense],
+            children: vec![
+                ReportNode {
+                    header: "Revenue".to_string(),
+                    types: vec![Type::Revenue],
+                    ..Default::default()
+                },
+                ReportNode {
+                    header: "Expenses".to_string(),
+                    types: vec![Type::Expense],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        Self::apply_balances(
+            chart,
+            &mut report,
+            self.balances_between(from, until, Vec::new(), None).await?,
+        )?;
+        Ok(report)
+    }
+
+    /// Standard balance sheet as of `as_of` (defaults to today) grouped by account type, with
+    /// revenue and expenses to date under equity as retained earnings so that it balances
+    pub async fn balance_sheet(
+        &self,
+        chart: &ChartOfAccounts,
+        as_of: Option<NaiveDate>,
+    ) -> Result<ReportNode> {
+        let mut report = ReportNode {
+            header: "Balance Sheet".to_string(),
+            types: vec![
+                Type::Asset,
+                Type::Liability,
+                Type::Equity,
+                Type::Revenue,
+                Type::Expense,
+            ],
+            children: vec![
+                ReportNode {
+                    header: "Assets".to_string(),
+                    types: vec![Type::Asset],
+                    ..Default::default()
+                },
+                ReportNode {
+                    header: "Liabilities".to_string(),
+                    types: vec![Type::Liability],
+                    ..Default::default()
+                },
+                ReportNode {
+                    header: "Equity".to_string(),
+                    types: vec![Type::Equity, Type::Revenue, Type::Expense],
+                    children: vec![ReportNode {
+                        header: "Retained Earnings".to_string(),
+                        types: vec![Type::Revenue, Type::Expense],
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        Self::apply_balances(chart, &mut report, self.balances_as_of(None, as_of).await?)?;
+        Ok(report)
+    }
+
+    /// Get net and gross revenue of each party's sale invoices dated from `from` up to `until`
+    /// (defaults to today). Extras posted to `tax_account` are counted as tax and any other
+    /// extras as revenue, so net matches what the revenue accounts hold.
+    pub async fn revenue(
+        &self,
+        from: Option<NaiveDate>,
+        until: Option<NaiveDate>,
+        tax_account: &str,
+    ) -> Result<BTreeMap<String, Revenue>> {
+        let until = until.unwrap_or({
+            let today = Local::today();
+            NaiveDate::from_ymd(today.year(), today.month(), today.day())
+        });
+        self.entries()
+            .try_fold(BTreeMap::new(), |mut acc, entry| async move {
+                for date in entry
+                    .dates(until)
+                    .filter(|date| from.map_or(true, |f| *date >= f))
+                {
+                    if let EntryBody::SaleInvoice(invoice) = entry.body_on(date) {
+                        let subtotal = invoice.items_subtotal()?;
+                        let mut tax = Money::zero();
+                        for extra in invoice.extras.iter().flatten() {
+                            if extra.account == tax_account {
+                                tax += extra.total(subtotal)?;
+                            }
+                        }
+                        let revenue: &mut Revenue = acc.entry(invoice.party.clone()).or_default();
+                        revenue.net += invoice.total()? - tax;
+                        revenue.tax += tax;
+                    }
+                }
+                Ok(acc)
+            })
+            .await
+    }
+
+    /// Classify the movements of cash accounts, those tagged `cash` in `chart`, dated from `from`
+    /// up to `to` (defaults to today) by the tags of the accounts on the other side of each entry.
+    /// Counter-accounts tagged `investing` or `financing` go to those sections and any others to
+    /// operating, so the sections sum to the net change in cash.
+    pub async fn cash_flow_statement(
+        &self,
+        chart: &ChartOfAccounts,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<CashFlowStatement> {
+        let (cash, investing, financing) = (
+            &Tag::new("cash")?,
+            &Tag::new("investing")?,
+            &Tag::new("financing")?,
+        );
+        self.journal_grouped(Vec::new(), None, to)
+            .try_filter(|group| future::ready(from.map_or(true, |from| group.date >= from)))
+            .try_fold(
+                CashFlowStatement::default(),
+                |mut statement, group| async move {
+                    let mut counters = Vec::new();
+                    for line in group.lines.iter() {
+                        if !chart.get(&line.1)?.has_tag(cash) {
+                            counters.push(line);
+                        }
+                    }
+                    // entries not touching cash, or only moving it between cash accounts, aren't flows
+                    if counters.len() < group.lines.len() {
+                        for JournalEntry(_, account, amount, _, _) in counters {
+                            let account = chart.get(account)?;
+                            // cash moves opposite to its counter-accounts
+                            let flow = -amount.to_signed_money();
+                            let section = if account.has_tag(investing) {
+                                &mut statement.investing
+                            } else if account.has_tag(financing) {
+                                &mut statement.financing
+                            } else {
+                                &mut statement.operating
+                            };
+                            section.try_add_assign(flow)?;
+                        }
+                    }
+                    Ok(statement)
+                },
+            )
+            .await
+    }
+
+    /// Earliest and latest dates of own entries, counting every occurrence of recurring entries
+    /// that end, even in the future, and those of entries recurring without end up to today, or
+    /// None if there are no entries
+    pub async fn date_span(&self) -> Result<Option<(NaiveDate, NaiveDate)>> {
+        let today = Local::today();
+        let until = NaiveDate::from_ymd(today.year(), today.month(), today.day());
+        self.entries()
+            .try_fold(None, |span, entry| async move {
+                let dates: Box<dyn Iterator<Item = NaiveDate>> = match entry.all_dates() {
+                    Some(dates) => Box::new(dates),
+                    None => Box::new(entry.dates(until)),
+                };
+                Ok(dates.fold(span, |span, date| match span {
+                    Some((first, last)) => Some((date.min(first), date.max(last))),
+                    None => Some((date, date)),
+                }))
+            })
+            .await
+    }
+
+    /// Get the outstanding balance of each purchase and sales invoice occurrence after applying
+    /// payments in date order. Payments attached to an invoice are applied to it first and an
+    /// invoice they settle in full is left out. A payment referencing an invoice that isn't
+    /// outstanding is left unapplied and recorded for `unmatched_payments`.
+    pub async fn invoice_balances(&self) -> Result<Vec<InvoiceBalance>> {
+        let today = Local::today();
+        let until = NaiveDate::from_ymd(today.year(), today.month(), today.day());
+        let entries: Vec<Entry> = self.entries().try_collect().await?;
+        let mut purchases = Vec::new();
+        let mut sales = Vec::new();
+        let mut payments = Vec::new();
+        for entry in entries.iter() {
+            for date in entry.dates(until) {
+                match entry.body_on(date) {
+                    EntryBody::PurchaseInvoice(invoice) => {
+                        purchases.extend(InvoiceBalance::unpaid(entry.id(), date, invoice)?)
+                    }
+                    EntryBody::SaleInvoice(invoice) => {
+                        sales.extend(InvoiceBalance::unpaid(entry.id(), date, invoice)?)
+                    }
+                    EntryBody::PaymentSent(payment) => {
+                        payments.push((date, entry.id(), true, payment))
+                    }
+                    EntryBody::PaymentReceived(payment) => {
+                        payments.push((date, entry.id(), false, payment))
+                    }
+                    EntryBody::Split(_) | EntryBody::GeneralJournal(_) => {}
+                }
+            }
+        }
+        purchases.sort_by_key(|invoice| invoice.date);
+        sales.sort_by_key(|invoice| invoice.date);
+        payments.sort_by_key(|(date, _, _, _)| *date);
+        for (date, id, sent, payment) in payments.iter() {
+            let invoices = if *sent { &mut purchases } else { &mut sales };
+            if !InvoiceBalance::allocate(invoices, *date, payment) {
+                if let Ok(mut unmatched) = self.unmatched.lock() {
+                    unmatched.push((id.clone(), payment.invoice.clone().unwrap_or_default()));
+                }
+            }
+        }
+        purchases.append(&mut sales);
+        Ok(purchases)
+    }
+
+    /// Get Accounts Payable balance of each party as of `until` (defaults to today)
+    pub fn payable(
+        &self,
+        until: Option<NaiveDate>,
+    ) -> impl Future<Output = Result<HashMap<String, JournalAmount>>> + '_ {
+        self.journal_filtered(Vec::new(), None, until).try_fold(
             HashMap::new(),
-            |mut acc, JournalEntry(_, account, amount, party)| async move {
+            |mut acc, JournalEntry(_, account, amount, party, _)| async move {
                 if account == "Accounts Payable" {
                     if let Some(party) = party {
                         acc.entry(party)
-                            .and_modify(|total: &mut JournalAmount| {
-                                total.add_assign(amount);
-                            })
-                            .or_insert(amount);
+                            .or_insert_with(JournalAmount::default)
+                            .try_add_assign(amount)?;
                     }
                 }
                 Ok(acc)
@@ -136,17 +1336,42 @@ impl Ledger {
         )
     }
 
-    pub fn receivable(&self) -> impl Future<Output = Result<HashMap<String, JournalAmount>>> + '_ {
-        self.journal(None).try_fold(
+    /// Get Accounts Receivable balance of each party as of `until` (defaults to today)
+    pub fn receivable(
+        &self,
+        until: Option<NaiveDate>,
+    ) -> impl Future<Output = Result<HashMap<String, JournalAmount>>> + '_ {
+        self.journal_filtered(Vec::new(), None, until).try_fold(
             HashMap::new(),
-            |mut acc, JournalEntry(_, account, amount, party)| async move {
+            |mut acc, JournalEntry(_, account, amount, party, _)| async move {
                 if account == "Accounts Receivable" {
                     if let Some(party) = party {
                         acc.entry(party)
-                            .and_modify(|total: &mut JournalAmount| {
-                                total.add_assign(amount);
-                            })
-                            .or_insert(amount);
+                            .or_insert_with(JournalAmount::default)
+                            .try_add_assign(amount)?;
+                    }
+                }
+                Ok(acc)
+            },
+        )
+    }
+
+    /// Get the Accounts Payable and Accounts Receivable balances of each party together as of
+    /// `until` (defaults to today), in a single pass over the journal
+    pub fn party_balances(
+        &self,
+        until: Option<NaiveDate>,
+    ) -> impl Future<Output = Result<HashMap<String, (JournalAmount, JournalAmount)>>> + '_ {
+        self.journal_filtered(Vec::new(), None, until).try_fold(
+            HashMap::new(),
+            |mut acc: HashMap<_, (JournalAmount, JournalAmount)>,
+             JournalEntry(_, account, amount, party, _)| async move {
+                if let Some(party) = party {
+                    let balances = || acc.entry(party).or_default();
+                    match account.as_str() {
+                        "Accounts Payable" => balances().0.try_add_assign(amount)?,
+                        "Accounts Receivable" => balances().1.try_add_assign(amount)?,
+                        _ => {}
                     }
                 }
                 Ok(acc)
@@ -1,13 +1,21 @@
 // use accounts;
 use accounts::{chart_of_accounts::ChartOfAccounts, *};
-use anyhow::Result;
-use clap::{Arg, Command};
-use futures::stream::TryStreamExt;
-use std::fs;
+use anyhow::{Context, Result};
+use async_std::task;
+use chrono::{Local, NaiveDate};
+use clap::{Arg, ArgMatches, Command};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use journal_entry::{JournalAmount, RowFormat};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+use std::{env, fs, iter};
 
-#[async_std::main]
-async fn main() -> Result<()> {
-    let matches = Command::new("Accounts")
+fn cli() -> Command<'static> {
+    Command::new("Accounts")
         .version("0.1.0")
         .author("Luke Nimtz <luke.nimtz@gmail.com>")
         .about("Simple accounting tools")
@@ -16,11 +24,19 @@ async fn main() -> Result<()> {
             Arg::new("entries")
                 .short('e')
                 .long("entries")
-                .help("Sets directory or file of entries or '-' for stdin ")
+                .help("Sets directory or file of entries or '-' for stdin")
                 .value_name("DIR")
                 .default_value("./")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("entry format")
+                .long("entry-format")
+                .help("Reads entries as YAML documents or as CSV rows under a header row")
+                .possible_values(["yaml", "csv"])
+                .default_value("yaml")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("party")
                 .short('p')
@@ -29,97 +45,1086 @@ async fn main() -> Result<()> {
                 .value_name("PARTY")
                 .takes_value(true),
         )
-        .subcommand(Command::new("journal").about("Shows journal"))
-        .subcommand(Command::new("balances").about("Shows account balances"))
-        .subcommand(
-            Command::new("report")
-                .about("Runs report given report spec and chart of accounts")
-                .arg(
-                    Arg::new("report spec")
-                        .short('s')
-                        .long("spec")
-                        .help("The report spec file")
-                        .value_name("FILE")
-                        .takes_value(true)
-                        .required(true),
+        .arg(
+            Arg::new("account")
+                .short('a')
+                .long("account")
+                .help(
+                    "Filters journal and balances to the given account, a glob such as 'Bank*' \
+                    or a regex prefixed with 're:' (may be repeated)",
+                )
+                .value_name("ACCOUNT")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("ref")
+                .long("ref")
+                .help("Only reads entries with the given reference number (e.g. invoice number)")
+                .value_name("NUMBER")
+                .takes_value(true),
+        )
+        .arg(Arg::new("dedup").long("dedup").help(
+            "Ignores repeated entries with the same id, warning if their content differs, \
+                and entries without an id repeated exactly in another file",
+        ))
+        .arg(
+            Arg::new("no recurring")
+                .long("no-recurring")
+                .help("Takes only the first occurrence of recurring entries"),
+        )
+        .arg(
+            Arg::new("date order")
+                .long("date-order")
+                .help(
+                    "Reads dates not written as YYYY-MM-DD month first or day first, \
+                    rather than rejecting those that could be either",
                 )
-                .arg(
-                    Arg::new("chart of accounts")
-                        .short('c')
-                        .long("chart")
-                        .help("The Chart of Accounts file")
-                        .value_name("FILE")
-                        .takes_value(true)
-                        .required(true),
-                ),
+                .possible_values(["month-first", "day-first"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("fiscal year start")
+                .long("fiscal-year-start")
+                .help("Sets the month and day fiscal years start on (MM-DD)")
+                .value_name("MM-DD")
+                .default_value("01-01")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .help("Colors debits green and credits red unless NO_COLOR is set"),
         )
-        .subcommand(Command::new("payable").about("Shows accounts payable balances by party"))
-        .subcommand(Command::new("receivable").about("Shows accounts receivable balances by party"))
-        .get_matches();
+        .arg(
+            Arg::new("zero dash")
+                .long("zero-dash")
+                .help("Shows zero balances as a dash rather than $0.00"),
+        )
+        .subcommands(commands())
+        .subcommand(
+            Command::new("watch")
+                .about("Re-runs a command whenever the entries change")
+                .subcommand_required(true)
+                .subcommands(commands()),
+        )
+}
+
+/// Commands that can be run once or watched
+fn commands() -> Vec<Command<'static>> {
+    vec![
+        Command::new("journal")
+            .about("Shows journal")
+            .arg(Arg::new("dir sorted").long("dir-sorted").help(
+                "Streams lines without collecting the whole journal for entries in date \
+                    order by file name, erroring if dates go backwards",
+            ))
+            .arg(
+                Arg::new("grouped")
+                    .short('g')
+                    .long("grouped")
+                    .help("Shows the lines of each entry together under its date and memo")
+                    .conflicts_with("dir sorted"),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .help("Output as a table, a JSON array or CSV rows of journal lines")
+                    .takes_value(true)
+                    .possible_values(["table", "json", "csv"])
+                    .default_value("table")
+                    .conflicts_with("grouped"),
+            )
+            .arg(
+                until_arg()
+                    .help("Shows journal lines up to the given date (YYYY-MM-DD) instead of today"),
+            ),
+        Command::new("balances")
+            .about("Shows account balances")
+            .arg(
+                Arg::new("from")
+                    .short('f')
+                    .long("from")
+                    .help("Totals only lines from the given date (YYYY-MM-DD)")
+                    .value_name("DATE")
+                    .takes_value(true),
+            )
+            .arg(until_arg().alias("to"))
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .help("Output as a table or as newline delimited JSON, one account per line")
+                    .takes_value(true)
+                    .possible_values(["table", "ndjson"])
+                    .default_value("table"),
+            )
+            .arg(
+                Arg::new("chart of accounts")
+                    .short('c')
+                    .long("chart")
+                    .help("The Chart of Accounts file or dir of files")
+                    .value_name("FILE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("strict")
+                    .long("strict")
+                    .help("Fails if the balances don't net to zero, unless filtered by --account"),
+            )
+            .arg(
+                Arg::new("warn abnormal")
+                    .long("warn-abnormal")
+                    .help("Marks accounts in the table whose balance is opposite their normal side")
+                    .requires("chart of accounts"),
+            )
+            .arg(
+                Arg::new("nonnegative")
+                    .long("nonnegative")
+                    .help("Warns of accounts with the given tag whose balance ever goes negative")
+                    .value_name("TAG")
+                    .takes_value(true)
+                    .requires("chart of accounts"),
+            )
+            .arg(
+                Arg::new("by class")
+                    .long("by-class")
+                    .help("Groups accounts by class with a subtotal each and checks they balance")
+                    .requires("chart of accounts"),
+            )
+            .arg(Arg::new("lines").long("lines").help(
+                "Lists the journal lines of each account given by --account before its total",
+            ))
+            .arg(
+                Arg::new("natural")
+                    .long("natural")
+                    .help("Shows each balance signed so it's positive on the account's normal side")
+                    .requires("chart of accounts")
+                    .conflicts_with("warn abnormal"),
+            ),
+        Command::new("diff")
+            .about("Shows differences in account balances against another ledger")
+            .arg(
+                Arg::new("other")
+                    .short('o')
+                    .long("other")
+                    .help("Sets directory or file of entries to compare against")
+                    .value_name("DIR")
+                    .takes_value(true)
+                    .required(true),
+            ),
+        Command::new("report")
+            .about("Runs report given report spec and chart of accounts")
+            .arg(
+                Arg::new("report spec")
+                    .short('s')
+                    .long("spec")
+                    .help("The report spec file")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("overlay")
+                    .long("overlay")
+                    .help(
+                        "Merges a spec into the report spec, adding or overriding nodes by header",
+                    )
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .multiple_occurrences(true),
+            )
+            .arg(
+                Arg::new("chart of accounts")
+                    .short('c')
+                    .long("chart")
+                    .help("The Chart of Accounts file or dir of files")
+                    .value_name("FILE")
+                    .takes_value(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::new("round")
+                    .long("round")
+                    .help("Rounds displayed totals to whole dollars"),
+            )
+            .arg(
+                Arg::new("as of")
+                    .long("as-of")
+                    .help("Runs the report on balances as of the given date (YYYY-MM-DD)")
+                    .value_name("DATE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("title")
+                    .long("title")
+                    .help(
+                        "Heads the report with a title, replacing {from} with the date of the \
+                        first entry and {to} with the date the report is as of",
+                    )
+                    .value_name("TITLE")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("detail")
+                    .long("detail")
+                    .help("Lists the accounts making up each total"),
+            )
+            .arg(
+                Arg::new("indent")
+                    .long("indent")
+                    .help("Sets the number of spaces to indent each level by (default 2)")
+                    .value_name("N")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("tree")
+                    .long("tree")
+                    .help("Draws tree connectors between each level"),
+            )
+            .arg(
+                Arg::new("percent of")
+                    .long("percent-of")
+                    .help("Shows each total as a percentage of its parent's total")
+                    .value_name("BASIS")
+                    .possible_values(["parent"])
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("strict")
+                    .long("strict")
+                    .help("Fails rather than warns when an account matches more than one node"),
+            )
+            .arg(
+                Arg::new("depth")
+                    .long("depth")
+                    .help("Shows only this many levels, rolling deeper totals up into the last")
+                    .value_name("N")
+                    .takes_value(true),
+            ),
+        Command::new("fmt")
+            .about("Prints entries in normalised form, or rewrites the files with --write")
+            .arg(
+                Arg::new("write")
+                    .short('w')
+                    .long("write")
+                    .help("Rewrites each entry file in place, keeping comments leading an entry but no others"),
+            ),
+        Command::new("init-chart")
+            .about("Prints a starter chart of accounts with guessed types for accounts in entries"),
+        Command::new("revenue")
+            .about("Shows sales revenue by party, net of sales tax unless --gross")
+            .arg(
+                Arg::new("tax account")
+                    .long("tax-account")
+                    .help("The account sales tax is collected to")
+                    .value_name("ACCOUNT")
+                    .default_value("Sales Tax Payable")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::new("from")
+                    .short('f')
+                    .long("from")
+                    .help("Includes sales from the given date (YYYY-MM-DD)")
+                    .value_name("DATE")
+                    .takes_value(true),
+            )
+            .arg(until_arg())
+            .arg(
+                Arg::new("year")
+                    .short('y')
+                    .long("year")
+                    .help("Includes sales in the given fiscal year")
+                    .value_name("YEAR")
+                    .takes_value(true)
+                    .conflicts_with_all(&["from", "until"]),
+            )
+            .arg(
+                Arg::new("gross")
+                    .long("gross")
+                    .help("Shows revenue including sales tax collected"),
+            ),
+        Command::new("payable")
+            .about("Shows accounts payable balances by party")
+            .arg(until_arg())
+            .arg(sort_arg()),
+        Command::new("receivable")
+            .about("Shows accounts receivable balances by party")
+            .arg(until_arg())
+            .arg(sort_arg()),
+    ]
+}
+
+/// Date to show balances as of
+fn until_arg() -> Arg<'static> {
+    Arg::new("until")
+        .short('u')
+        .long("until")
+        .help("Shows balances as of the given date (YYYY-MM-DD) instead of today")
+        .value_name("DATE")
+        .takes_value(true)
+}
+
+/// Order to list party balances in
+fn sort_arg() -> Arg<'static> {
+    Arg::new("sort")
+        .long("sort")
+        .help("Sorts by party name or by largest balance first")
+        .value_name("ORDER")
+        .possible_values(["party", "amount"])
+        .default_value("party")
+        .takes_value(true)
+}
+
+/// Parse the `until` date of a subcommand if given
+fn until(ledger: &Ledger, command: &ArgMatches) -> Result<Option<NaiveDate>> {
+    date_arg(ledger, command, "until")
+}
+
+/// Parse the date argument `name` of a subcommand if given, the same way as entry dates
+fn date_arg(ledger: &Ledger, command: &ArgMatches, name: &str) -> Result<Option<NaiveDate>> {
+    command
+        .value_of(name)
+        .map(|date| ledger.parse_date(&format!("{} date", name), date))
+        .transpose()
+}
+
+#[async_std::main]
+async fn main() -> Result<()> {
+    let matches = cli().get_matches();
 
     if let Some(entries) = matches.value_of("entries") {
-        let ledger = if entries == "-" {
+        let mut ledger = if entries == "-" {
             Ledger::new(None)
         } else {
             Ledger::new(Some(entries))
         };
-        if matches.subcommand_matches("journal").is_some() {
+        if matches.is_present("dedup") {
+            ledger = ledger.dedup();
+        }
+        if matches.is_present("no recurring") {
+            ledger = ledger.no_recurring();
+        }
+        if let Some(reference) = matches.value_of("ref") {
+            ledger = ledger.with_reference(reference);
+        }
+        if let Some(format) = matches.value_of("entry format") {
+            ledger = ledger.with_format(format.parse()?);
+        }
+        if let Some(order) = matches.value_of("date order") {
+            ledger = ledger.with_date_order(order.parse()?);
+        }
+        if let Some(start) = matches.value_of("fiscal year start") {
+            let (month, day) = start
+                .split_once('-')
+                .context("Fiscal year start must be MM-DD")?;
+            ledger = ledger.with_fiscal_year_start(month.parse()?, day.parse()?)?;
+        }
+        if let Some(watch) = matches.subcommand_matches("watch") {
+            let mut last_modified = None;
+            loop {
+                let modified = ledger.last_modified().await?;
+                if modified != last_modified {
+                    last_modified = modified;
+                    // clear screen before printing refreshed output
+                    print!("\x1b[2J\x1b[H");
+                    if let Err(err) = run(&matches, watch, &ledger, &mut io::stdout()).await {
+                        eprintln!("Error: {:?}", err);
+                    }
+                    print_skipped(&ledger);
+                }
+                task::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        run(&matches, &matches, &ledger, &mut io::stdout().lock()).await?;
+        print_skipped(&ledger);
+    };
+    Ok(())
+}
+
+/// Load a chart of accounts from a single file or a dir of files
+async fn load_chart(path: &str) -> Result<ChartOfAccounts> {
+    if Path::new(path).is_dir() {
+        ChartOfAccounts::from_dir(path).await
+    } else {
+        ChartOfAccounts::from_file(path).await
+    }
+}
+
+fn print_skipped(ledger: &Ledger) {
+    for (path, err) in ledger.skipped_files() {
+        eprintln!("Skipped {}: {}", path, err);
+    }
+    for (id, path) in ledger.id_collisions() {
+        eprintln!(
+            "Warning: ignored entry {} in {}, its id is already used by an entry with different content",
+            id, path
+        );
+    }
+    for (id, invoice) in ledger.unmatched_payments() {
+        eprintln!(
+            "Warning: left payment {} unapplied, it references {} which isn't an outstanding invoice of its party dated on or before it",
+            id, invoice
+        );
+    }
+}
+
+/// Runs the subcommand of `command` against the ledger, writing the output to `out` as it goes.
+/// Global options are read from `matches`.
+async fn run(
+    matches: &ArgMatches,
+    command: &ArgMatches,
+    ledger: &Ledger,
+    out: &mut impl Write,
+) -> Result<()> {
+    let color = matches.is_present("color") && env::var_os("NO_COLOR").is_none();
+    let zero_dash = matches.is_present("zero dash");
+    let accounts: Vec<String> = matches
+        .values_of("account")
+        .map_or_else(Vec::new, |values| values.map(ToOwned::to_owned).collect());
+    if let Some(journal) = command
+        .subcommand_matches("journal")
+        .filter(|journal| journal.is_present("grouped"))
+    {
+        let mut groups: Vec<journal_entry::JournalGroup> = ledger
+            .journal_grouped(
+                accounts,
+                matches.value_of("party").map(ToOwned::to_owned),
+                until(ledger, journal)?,
+            )
+            .try_collect()
+            .await?;
+        groups.sort_by_key(|group| group.date);
+        for (i, group) in groups.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            write!(out, "{}", group)?;
+        }
+    } else if let Some(journal) = command.subcommand_matches("journal") {
+        let party = matches.value_of("party").map(ToOwned::to_owned);
+        let until = until(ledger, journal)?;
+        let mut journal_entries = if journal.is_present("dir sorted") {
+            Box::pin(ledger.journal_sorted(accounts, party, until).left_stream())
+        } else {
             let mut journal_entries: Vec<journal_entry::JournalEntry> = ledger
-                .journal(matches.value_of("party").map(ToOwned::to_owned))
+                .journal_filtered(accounts, party, until)
                 .try_collect()
                 .await?;
-            if let Some(party) = matches.value_of("party") {
-                journal_entries = journal_entries
-                    .into_iter()
-                    .filter(|entry| entry.3.clone().map_or(false, |p| p == party))
-                    .collect()
-            }
             journal_entries.sort_by_key(|x| x.0);
-            journal_entries.into_iter().for_each(|entry| {
-                println!("{}", entry);
-            });
-        } else if matches.subcommand_matches("balances").is_some() {
-            let balances = ledger
-                .balances(matches.value_of("party").map(ToOwned::to_owned))
+            Box::pin(stream::iter(journal_entries.into_iter().map(Ok)).right_stream())
+        };
+        if journal.value_of("format") == Some("json") {
+            let journal_entries: Vec<journal_entry::JournalEntry> =
+                journal_entries.try_collect().await?;
+            writeln!(out, "{}", serde_json::to_string_pretty(&journal_entries)?)?;
+        } else if journal.value_of("format") == Some("csv") {
+            // under a header taken from the first row
+            let mut writer = csv::Writer::from_writer(&mut *out);
+            while let Some(entry) = journal_entries.try_next().await? {
+                writer.serialize(entry)?;
+            }
+            writer.flush()?;
+        } else {
+            while let Some(entry) = journal_entries.try_next().await? {
+                writeln!(out, "{}", entry)?;
+            }
+        }
+    } else if let Some(balances_args) = command.subcommand_matches("balances") {
+        let filtered = !accounts.is_empty();
+        let balances = ledger
+            .balances_between(
+                date_arg(ledger, balances_args, "from")?,
+                until(ledger, balances_args)?,
+                accounts,
+                matches.value_of("party").map(ToOwned::to_owned),
+            )
+            .await?;
+        // a subset of accounts isn't expected to balance
+        if balances_args.is_present("strict") && !filtered {
+            Ledger::assert_balanced(&balances)?;
+        }
+        if balances_args.value_of("format") == Some("ndjson") {
+            #[derive(Serialize)]
+            struct BalanceLine<'a> {
+                account: &'a str,
+                #[serde(flatten)]
+                amount: &'a JournalAmount,
+            }
+            let mut balances: Vec<_> = balances.iter().collect();
+            balances.sort_by_key(|(account, _)| *account);
+            for (account, amount) in balances {
+                let line = serde_json::to_string(&BalanceLine { account, amount })?;
+                writeln!(out, "{}", line)?;
+            }
+        } else if let (Some(chart), true) = (
+            balances_args.value_of("chart of accounts"),
+            balances_args.is_present("by class"),
+        ) {
+            let chart = load_chart(chart).await?;
+            let class_totals = chart.class_totals(balances.iter())?;
+            let format = RowFormat {
+                color,
+                zero_dash,
+                ..RowFormat::fit(balances.values().chain(class_totals.iter().map(|x| &x.1)))
+            };
+            let mut accounts: Vec<_> = balances.iter().collect();
+            accounts.sort_by_key(|(account, _)| *account);
+            let mut net = JournalAmount::default();
+            for (class, total) in class_totals.iter() {
+                writeln!(out, "{}", class)?;
+                for (account, amount) in accounts.iter() {
+                    if chart.get(account)?.acc_type == *class {
+                        let account = format!("  {}", account);
+                        writeln!(out, "{:25} | {}", account, amount.to_row_string(&format))?;
+                    }
+                }
+                writeln!(out, "{:25} | {}", "SUBTOTAL", total.to_row_string(&format))?;
+                writeln!(out)?;
+                net += *total;
+            }
+            // a subset of accounts isn't expected to balance
+            if !filtered {
+                if net.money().signum() == 0 {
+                    writeln!(out, "Assets = Liabilities + Equity + (Revenue - Expenses)")?;
+                } else {
+                    writeln!(
+                        out,
+                        "ERROR: classes are out of balance by {}",
+                        net.to_row_string(&format)
+                    )?;
+                }
+            }
+        } else if balances_args.is_present("lines") {
+            if !filtered {
+                anyhow::bail!("--lines requires the accounts to list given by --account");
+            }
+            let general_ledger = ledger
+                .general_ledger(
+                    balances.keys().cloned().collect(),
+                    matches.value_of("party").map(ToOwned::to_owned),
+                )
                 .await?;
-            let total = balances.iter().fold(
-                journal_entry::JournalAmount::default(),
-                |mut acc, amount| {
-                    acc += *amount.1;
-                    acc
-                },
-            );
-            balances.iter().for_each(|(account, amount)| {
-                println!("{:25} | {}", account, amount);
-            });
-            if total != journal_entry::JournalAmount::default() {
-                println!("ERROR                     | {}", total);
-            }
-        } else if let Some(report) = matches.subcommand_matches("report") {
-            if let (Some(spec), Some(chart)) = (
-                report.value_of("report spec"),
-                report.value_of("chart of accounts"),
+            let format = RowFormat {
+                color,
+                zero_dash,
+                ..RowFormat::fit(
+                    general_ledger
+                        .values()
+                        .flatten()
+                        .map(|line| &line.2)
+                        .chain(balances.values()),
+                )
+            };
+            for (i, (account, lines)) in general_ledger.iter().enumerate() {
+                if i > 0 {
+                    writeln!(out)?;
+                }
+                writeln!(out, "{}", account)?;
+                for line in lines {
+                    writeln!(out, "{:25} | {}", line.0, line.2.to_row_string(&format))?;
+                }
+                if let Some(balance) = balances.get(account) {
+                    writeln!(out, "{:25} | {}", "TOTAL", balance.to_row_string(&format))?;
+                }
+            }
+        } else if let (Some(chart), true) = (
+            balances_args.value_of("chart of accounts"),
+            balances_args.is_present("natural"),
+        ) {
+            let chart = load_chart(chart).await?;
+            let natural = balances
+                .iter()
+                .map(|(account, amount)| {
+                    Ok((
+                        account,
+                        chart.get(account)?.natural_balance(amount).to_string(),
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let width = natural.iter().map(|(_, amount)| amount.len()).max();
+            for (account, amount) in natural {
+                writeln!(
+                    out,
+                    "{:25} | {:>w$}",
+                    account,
+                    amount,
+                    w = width.unwrap_or_default()
+                )?;
+            }
+        } else {
+            let total = journal_entry::JournalAmount::sum(balances.values().copied())?;
+            let format = RowFormat {
+                color,
+                zero_dash,
+                ..RowFormat::fit(balances.values().chain(iter::once(&total)))
+            };
+            let abnormal = match balances_args.value_of("chart of accounts") {
+                Some(chart) if balances_args.is_present("warn abnormal") => load_chart(chart)
+                    .await?
+                    .abnormal_balances(balances.iter())?,
+                _ => Vec::new(),
+            };
+            for (account, amount) in balances.iter() {
+                write!(out, "{:25} | {}", account, amount.to_row_string(&format))?;
+                if abnormal.contains(&account.as_str()) {
+                    write!(out, " | abnormal balance")?;
+                }
+                writeln!(out)?;
+            }
+            // a subset of accounts isn't expected to balance
+            if !filtered && total != journal_entry::JournalAmount::default() {
+                writeln!(
+                    out,
+                    "ERROR                     | {}",
+                    total.to_row_string(&format)
+                )?;
+            }
+            if let (Some(chart), Some(tag)) = (
+                balances_args.value_of("chart of accounts"),
+                balances_args.value_of("nonnegative"),
             ) {
-                let chart = ChartOfAccounts::from_file(chart).await?;
-                let mut report = fs::read_to_string(spec)?.parse()?;
-                let report = ledger.run_report(&chart, &mut report).await?;
-                println!("{}", report)
-            }
-        } else if matches.subcommand_matches("payable").is_some() {
-            let payables = ledger.payable().await?;
-            let mut payables: Vec<_> = payables.iter().collect();
-            payables.sort_by_key(|x| x.0);
-            payables.iter().for_each(|(account, amount)| {
-                println!("{:25} | {}", account, amount);
-            });
-        } else if matches.subcommand_matches("receivable").is_some() {
-            let receivables = ledger.receivable().await?;
-            let mut receivables: Vec<_> = receivables.iter().collect();
-            receivables.sort_by_key(|x| x.0);
-            receivables.iter().for_each(|(account, amount)| {
-                println!("{:25} | {}", account, amount);
-            });
+                let chart = load_chart(chart).await?;
+                let dips = ledger
+                    .negative_dips(&chart, &account::Tag::new(tag)?)
+                    .await?;
+                for (account, date) in dips {
+                    writeln!(out, "WARNING {} went negative on {}", account, date)?;
+                }
+            }
+        }
+    } else if let Some(diff) = command.subcommand_matches("diff") {
+        if let Some(other) = diff.value_of("other") {
+            let other = Ledger::new(Some(other));
+            let diff = ledger.diff_balances(&other).await?;
+            for (account, amount) in diff.iter() {
+                writeln!(out, "{:25} | {}", account, amount)?;
+            }
         }
+    } else if let Some(report) = command.subcommand_matches("report") {
+        if let (Some(spec), Some(chart)) = (
+            report.value_of("report spec"),
+            report.value_of("chart of accounts"),
+        ) {
+            let options = report::RenderOptions {
+                round: report.is_present("round"),
+                detail: report.is_present("detail"),
+                indent: report
+                    .value_of("indent")
+                    .map(|indent| {
+                        indent
+                            .parse()
+                            .with_context(|| format!("Invalid indent {}", indent))
+                    })
+                    .transpose()?
+                    .unwrap_or(2),
+                tree: report.is_present("tree"),
+                percent_of_parent: report.value_of("percent of") == Some("parent"),
+            };
+            let as_of = date_arg(ledger, report, "as of")?;
+            let title = report.value_of("title");
+            let strict = report.is_present("strict");
+            let chart = load_chart(chart).await?;
+            let depth = report
+                .value_of("depth")
+                .map(|depth| {
+                    depth
+                        .parse::<usize>()
+                        .with_context(|| format!("Invalid depth {}", depth))
+                })
+                .transpose()?;
+            let overlays = report
+                .values_of("overlay")
+                .into_iter()
+                .flatten()
+                .map(|overlay| fs::read_to_string(overlay)?.parse())
+                .collect::<Result<Vec<report::ReportNode>>>()?;
+            let mut report: report::ReportNode = fs::read_to_string(spec)?.parse()?;
+            for overlay in overlays.iter() {
+                report.merge_overlay(overlay);
+            }
+            let report = ledger.run_report(&chart, &mut report, as_of).await?;
+            let ambiguous = report
+                .ambiguous_accounts(chart.accounts())
+                .into_iter()
+                .map(|(account, nodes)| format!("{} matches {}", account, nodes.join(" and ")))
+                .collect::<Vec<_>>();
+            if strict && !ambiguous.is_empty() {
+                anyhow::bail!(
+                    "Accounts match more than one report node:\n{}",
+                    ambiguous.join("\n")
+                );
+            }
+            for ambiguous in ambiguous {
+                eprintln!("Warning: {}, only the first applies", ambiguous);
+            }
+            if let Some(title) = title {
+                let to = as_of.unwrap_or_else(|| Local::today().naive_local());
+                // only read the ledger again for the first date if it's wanted
+                let from = if title.contains("{from}") {
+                    ledger.date_span().await?.map_or(to, |(from, _)| from)
+                } else {
+                    to
+                };
+                writeln!(
+                    out,
+                    "{}",
+                    title
+                        .replace("{from}", &from.to_string())
+                        .replace("{to}", &to.to_string())
+                )?;
+            }
+            match depth {
+                Some(depth) => writeln!(
+                    out,
+                    "{}",
+                    report.collapse_below(depth).display_with(&options)
+                )?,
+                None => writeln!(out, "{}", report.display_with(&options))?,
+            }
+            let failed = report.failed_assertions()?;
+            if !failed.is_empty() {
+                anyhow::bail!(
+                    "Report totals don't match their assertions:\n{}",
+                    failed
+                        .iter()
+                        .map(|(path, expected, actual)| format!(
+                            "{}: asserted {} but totals {}, a difference of {}",
+                            path.join(" > "),
+                            expected,
+                            actual,
+                            *actual - *expected
+                        ))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                );
+            }
+        }
+    } else if let Some(fmt) = command.subcommand_matches("fmt") {
+        for (path, formatted) in ledger.formatted_files().await? {
+            if fmt.is_present("write") {
+                fs::write(&path, formatted)?;
+            } else {
+                write!(out, "# {}\n{}", path, formatted)?;
+            }
+        }
+    } else if command.subcommand_matches("init-chart").is_some() {
+        write!(out, "{}", ledger.init_chart().await?.to_yaml()?)?;
+    } else if let Some(revenue) = command.subcommand_matches("revenue") {
+        let tax_account = revenue.value_of("tax account").context("No tax account")?;
+        let (from, until) = match revenue.value_of("year") {
+            Some(year) => {
+                let (from, until) = ledger.fiscal_year_bounds(year.parse()?);
+                (Some(from), Some(until))
+            }
+            None => (date_arg(ledger, revenue, "from")?, until(ledger, revenue)?),
+        };
+        let revenues = ledger.revenue(from, until, tax_account).await?;
+        for (party, amounts) in revenues.iter() {
+            let amount = if revenue.is_present("gross") {
+                amounts.gross()
+            } else {
+                amounts.net
+            };
+            writeln!(out, "{:25} | {}", party, amount)?;
+        }
+    } else if let Some(payable) = command.subcommand_matches("payable") {
+        let payables = ledger.payable(until(ledger, payable)?).await?;
+        write_party_balances(out, payables, payable, color, zero_dash)?;
+    } else if let Some(receivable) = command.subcommand_matches("receivable") {
+        let receivables = ledger.receivable(until(ledger, receivable)?).await?;
+        write_party_balances(out, receivables, receivable, color, zero_dash)?;
+    }
+    Ok(())
+}
+
+/// Writes each party's balance sorted as requested followed by the total of all parties
+fn write_party_balances(
+    out: &mut impl Write,
+    balances: HashMap<String, JournalAmount>,
+    command: &ArgMatches,
+    color: bool,
+    zero_dash: bool,
+) -> Result<()> {
+    let mut balances: Vec<_> = balances.into_iter().collect();
+    balances.sort_by_key(|x| x.0.clone());
+    if command.value_of("sort") == Some("amount") {
+        // largest balances first, by party within equal balances
+        balances.sort_by_key(|x| Reverse(x.1.money().0));
+    }
+    let total = JournalAmount::sum(balances.iter().map(|(_, amount)| *amount))?;
+    let format = RowFormat {
+        color,
+        zero_dash,
+        ..RowFormat::fit(balances.iter().map(|x| &x.1).chain(iter::once(&total)))
     };
+    for (party, amount) in balances.iter() {
+        writeln!(out, "{:25} | {}", party, amount.to_row_string(&format))?;
+    }
+    writeln!(out, "{:25} | {}", "TOTAL", total.to_row_string(&format))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod main_tests {
+    use super::*;
+    use money::Money;
+
+    /// Output of `run` collected into a string
+    async fn run_output(
+        matches: &ArgMatches,
+        command: &ArgMatches,
+        ledger: &Ledger,
+    ) -> Result<String> {
+        let mut out = Vec::new();
+        run(matches, command, ledger, &mut out).await?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[async_std::test]
+    async fn watch_runs_same_as_single_run() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+        let single = cli().try_get_matches_from(vec!["accounts", "journal"])?;
+        let watched = cli().try_get_matches_from(vec!["accounts", "watch", "journal"])?;
+        let watch = watched
+            .subcommand_matches("watch")
+            .expect("watch subcommand");
+        let expected = run_output(&single, &single, &ledger).await?;
+        assert!(!expected.is_empty());
+        assert_eq!(run_output(&watched, watch, &ledger).await?, expected);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn balances_ndjson() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+        let matches =
+            cli().try_get_matches_from(vec!["accounts", "balances", "--format", "ndjson"])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        let balances = ledger.balances(None).await?;
+        let mut lines = 0;
+        for line in output.lines() {
+            let value: serde_json::Value = serde_json::from_str(line)?;
+            let account = value["account"].as_str().context("account")?;
+            let amount = match (value["debit"].as_str(), value["credit"].as_str()) {
+                (Some(debit), None) => JournalAmount::Debit(Money(debit.parse()?)),
+                (None, Some(credit)) => JournalAmount::Credit(Money(credit.parse()?)),
+                _ => anyhow::bail!("Expected one of debit or credit: {}", line),
+            };
+            assert_eq!(balances.get(account), Some(&amount), "{}", line);
+            lines += 1;
+        }
+        assert_eq!(lines, balances.len());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn balances_natural() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries_csv/expenses.csv"))
+            .with_format(entry::EntryFormat::Csv);
+        let matches = cli().try_get_matches_from(vec![
+            "accounts",
+            "balances",
+            "--chart",
+            "./tests/fixtures/ChartOfAccounts.yaml",
+            "--natural",
+        ])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        println!("{}", output);
+        let balances = ledger.balances(None).await?;
+        assert_eq!(
+            balances.get("Credit Card"),
+            Some(&JournalAmount::Credit("100".parse()?))
+        );
+        assert_eq!(
+            balances.get("Business Checking"),
+            Some(&JournalAmount::Debit("49.99".parse()?))
+        );
+        // a liability's credit and an asset's debit both read positive
+        assert!(output.contains("Credit Card               | $100.00\n"));
+        assert!(output.contains("Business Checking         |  $49.99\n"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn balances_lines() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+        let matches = cli().try_get_matches_from(vec![
+            "accounts",
+            "-a",
+            "Business Checking",
+            "balances",
+            "--lines",
+        ])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        println!("{}", output);
+        let general_ledger = ledger
+            .general_ledger(vec!["Business Checking".to_string()], None)
+            .await?;
+        let lines = &general_ledger["Business Checking"];
+        assert_eq!(output.lines().count(), lines.len() + 2);
+        let total = JournalAmount::sum(lines.iter().map(|line| line.2))?;
+        let balances = ledger.balances(None).await?;
+        assert_eq!(balances.get("Business Checking"), Some(&total));
+        assert!(output
+            .lines()
+            .last()
+            .map_or(false, |footer| footer.starts_with("TOTAL")
+                && footer.contains(&total.money().to_string())));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn party_balances_total() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries_duplicated"));
+        let matches =
+            cli().try_get_matches_from(vec!["accounts", "payable", "--sort", "amount"])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        println!("{}", output);
+        let payables = ledger.payable(None).await?;
+        let total = JournalAmount::sum(payables.values().copied())?;
+        let footer = output.lines().last().expect("footer");
+        assert!(footer.starts_with("TOTAL"));
+        assert!(
+            footer.contains(&total.money().to_string()),
+            "{} {}",
+            footer,
+            total.money()
+        );
+        assert_eq!(output.lines().count(), payables.len() + 1);
+        assert!(output.starts_with("ACME"), "largest balance first");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn journal_json() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+        let matches = cli().try_get_matches_from(vec![
+            "accounts",
+            "-p",
+            "John Smith",
+            "journal",
+            "--format",
+            "json",
+            "--until",
+            "2020-01-06",
+        ])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        let lines: Vec<serde_json::Value> = serde_json::from_str(&output)?;
+        let expected = ledger
+            .journal_filtered(
+                Vec::new(),
+                Some("John Smith".to_string()),
+                Some("2020-01-06".parse()?),
+            )
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(lines.len(), expected.len());
+        assert!(lines.iter().all(|line| line["party"] == "John Smith"));
+        assert!(lines.contains(&serde_json::json!({
+            "date": "2020-01-05",
+            "account": "Widget Sales",
+            "debit": null,
+            "credit": "10.00",
+            "party": "John Smith",
+            "reference": null,
+        })));
+        assert!(!lines.iter().any(|line| line["date"] == "2020-01-07"));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn journal_unsorted() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries_unsorted"));
+        let matches = cli().try_get_matches_from(vec!["accounts", "journal"])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        let dates: Vec<&str> = output.lines().map(|line| &line[..10]).collect();
+        assert_eq!(dates.len(), 6);
+        assert!(
+            dates.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{}",
+            output
+        );
+        let matches = cli().try_get_matches_from(vec!["accounts", "journal", "--dir-sorted"])?;
+        let err = run_output(&matches, &matches, &ledger).await.unwrap_err();
+        assert!(err.to_string().contains("must be in date order"), "{}", err);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn journal_csv() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+        let matches = cli().try_get_matches_from(vec![
+            "accounts",
+            "-p",
+            "John Smith",
+            "journal",
+            "--format",
+            "csv",
+            "--until",
+            "2020-01-06",
+        ])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        let mut rows = output.lines();
+        assert_eq!(
+            rows.next(),
+            Some("date,account,debit,credit,party,reference")
+        );
+        assert!(rows
+            .clone()
+            .any(|row| row == "2020-01-05,Widget Sales,,10.00,John Smith,"));
+        assert!(rows.all(|row| row.contains(",John Smith,")));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn report_title() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+        let matches = cli().try_get_matches_from(vec![
+            "accounts",
+            "report",
+            "--spec",
+            "./tests/fixtures/IncomeStatement.yaml",
+            "--chart",
+            "./tests/fixtures/ChartOfAccounts.yaml",
+            "--as-of",
+            "2020-01-31",
+            "--title",
+            "Income Statement {from} to {to}",
+        ])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        assert!(
+            output.starts_with("Income Statement 2020-01-01 to 2020-01-31\nIncome Statement "),
+            "{}",
+            output
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn empty_ledger() -> Result<()> {
+        let ledger = Ledger::new(Some("./tests/fixtures/entries_empty"));
+        for args in [
+            vec!["accounts", "balances"],
+            vec!["accounts", "journal"],
+            vec!["accounts", "journal", "--grouped"],
+        ] {
+            let matches = cli().try_get_matches_from(args)?;
+            assert_eq!(run_output(&matches, &matches, &ledger).await?, "");
+        }
+        let matches = cli().try_get_matches_from(vec![
+            "accounts",
+            "report",
+            "--spec",
+            "./tests/fixtures/IncomeStatement.yaml",
+            "--chart",
+            "./tests/fixtures/ChartOfAccounts.yaml",
+        ])?;
+        let output = run_output(&matches, &matches, &ledger).await?;
+        assert!(output.starts_with("Income Statement"));
+        assert!(output
+            .lines()
+            .all(|line| line.is_empty() || line.ends_with("$0")));
+        Ok(())
+    }
+}
@@ -1,11 +1,16 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use rust_decimal::prelude::*;
+use serde::{Serialize, Serializer};
 use std::cmp::Eq;
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::*;
 
-#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, PartialOrd)]
+/// How amounts are rounded, such as half-up or banker's half-even, re-exported for callers of
+/// `Money::round_with`
+pub use rust_decimal::RoundingStrategy;
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
 pub struct Money(pub Decimal);
 
 /// Basically this holds a Decimal that is scaled out to at least 2 dp (doesn't round).
@@ -21,6 +26,165 @@ impl TryFrom<f64> for Money {
     }
 }
 
+/// Parsed from a decimal string and, like conversion from f64, scaled out to at least 2 dp.
+/// Any extra precision is preserved rather than rounded. A `$` and `,` separators are allowed,
+/// and negatives may be written in parentheses as accountants do, such as `($60.50)`.
+impl FromStr for Money {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let trimmed = s.trim();
+        let (mut symbols, unsigned) = match trimmed.strip_prefix('$') {
+            Some(unsigned) => (1, unsigned),
+            None => (0, trimmed),
+        };
+        let (parenthesized, inner) = match (unsigned.strip_prefix('('), unsigned.ends_with(')')) {
+            (Some(inner), true) => (true, &inner[..inner.len() - 1]),
+            (None, false) => (false, unsigned),
+            _ => bail!("Unbalanced parentheses in {}", s),
+        };
+        // a minus sign and the currency symbol may come in either order, and a minus sign in
+        // parentheses is redundant rather than cancelling them out
+        let mut digits = inner.trim();
+        let mut minus = false;
+        loop {
+            if let Some(rest) = digits.strip_prefix('-').filter(|_| !minus) {
+                minus = true;
+                digits = rest;
+            } else if let Some(rest) = digits.strip_prefix('$') {
+                symbols += 1;
+                digits = rest;
+            } else {
+                break;
+            }
+        }
+        if symbols > 1 {
+            bail!("More than one currency symbol in {}", s);
+        }
+        let whole = digits.split('.').next().unwrap_or_default();
+        if whole.contains(',') {
+            let mut groups = whole.split(',');
+            let first = groups.next().unwrap_or_default();
+            let grouped = (1..=3).contains(&first.len())
+                && groups.all(|group| group.len() == 3)
+                && whole.chars().all(|c| c.is_ascii_digit() || c == ',');
+            if !grouped {
+                bail!("Invalid thousands separators in {}", s);
+            }
+        }
+        let mut d = Decimal::from_str(&digits.replace(',', ""))
+            .ok()
+            .filter(|_| digits.starts_with(|c: char| c.is_ascii_digit() || c == '.'))
+            .with_context(|| format!("Failed to parse {} as Money", s))?;
+        if parenthesized || minus {
+            d = -d;
+        }
+        if d.scale() < 2 {
+            d.rescale(2);
+        }
+        Ok(Self(d))
+    }
+}
+
+impl Money {
+    /// Round to `dp` decimal places using the given strategy.
+    pub fn round_with(&self, dp: u32, strategy: RoundingStrategy) -> Self {
+        Money(self.0.round_dp_with_strategy(dp, strategy))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0.is_sign_positive() && !self.0.is_zero()
+    }
+
+    /// -1, 0 or 1 by sign. Zero is 0 even if it carries a negative sign.
+    pub fn signum(&self) -> i32 {
+        if self.is_negative() {
+            -1
+        } else if self.is_positive() {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Total of `amounts`, zero if there are none, erroring on overflow
+    pub fn sum<I: IntoIterator<Item = Money>>(amounts: I) -> Result<Money> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(), |sum, amount| sum.try_add(amount))
+    }
+
+    /// Magnitude of the amount, without its sign
+    pub fn abs(self) -> Money {
+        Money(self.0.abs())
+    }
+
+    /// Add without panicking, erroring if the sum overflows
+    pub fn try_add(self, other: Money) -> Result<Money> {
+        self.0
+            .checked_add(other.0)
+            .map(Money)
+            .with_context(|| format!("Overflow adding {} to {}", other, self))
+    }
+
+    /// Add in place without panicking, erroring and leaving self unchanged on overflow
+    pub fn try_add_assign(&mut self, other: Money) -> Result<()> {
+        *self = self.try_add(other)?;
+        Ok(())
+    }
+
+    /// Subtract, or None if the result would be negative
+    pub fn checked_sub_nonneg(self, other: Money) -> Option<Money> {
+        let difference = Money(self.0.checked_sub(other.0)?);
+        (!difference.is_negative()).then_some(difference)
+    }
+
+    /// Split into cent accurate shares proportional to `weights` that sum exactly to the amount,
+    /// handing leftover cents to the largest remainders (earliest first on ties)
+    pub fn allocate(&self, weights: &[Decimal]) -> Result<Vec<Money>> {
+        let sum = weights
+            .iter()
+            .fold(Decimal::zero(), |sum, weight| sum + weight);
+        if sum.is_zero() || weights.iter().any(|weight| weight.is_sign_negative()) {
+            bail!("Weights must be positive");
+        }
+        let cents = (self.0.abs() * Decimal::new(100, 0)).round();
+        let exact: Vec<Decimal> = weights.iter().map(|weight| cents * weight / sum).collect();
+        let mut shares: Vec<Decimal> = exact.iter().map(|share| share.trunc()).collect();
+        let allocated = shares
+            .iter()
+            .fold(Decimal::zero(), |sum, share| sum + share);
+        let mut order: Vec<usize> = (0..shares.len()).collect();
+        order.sort_by(|a, b| (exact[*b] - shares[*b]).cmp(&(exact[*a] - shares[*a])));
+        let leftover = (cents - allocated).to_usize().unwrap_or_default();
+        for i in order.into_iter().take(leftover) {
+            shares[i] += Decimal::one();
+        }
+        Ok(shares
+            .into_iter()
+            .map(|share| {
+                let mut share = share / Decimal::new(100, 0);
+                share.rescale(2);
+                let share = Money(share);
+                if self.is_negative() {
+                    -share
+                } else {
+                    share
+                }
+            })
+            .collect())
+    }
+
+    /// Nearest f64, as used for amounts in entry files
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or_default()
+    }
+}
+
 impl Zero for Money {
     fn zero() -> Self {
         Money(Decimal::zero())
@@ -33,7 +197,7 @@ impl Zero for Money {
 
 impl fmt::Display for Money {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.0.is_sign_negative() {
+        if self.is_negative() {
             write!(f, "(${})", -self.0)
         } else {
             write!(f, "${}", self.0)
@@ -41,6 +205,13 @@ impl fmt::Display for Money {
     }
 }
 
+/// Serialized as a decimal string so no precision is lost
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&self.0)
+    }
+}
+
 impl Add for Money {
     type Output = Money;
 
@@ -57,6 +228,51 @@ impl Sub for Money {
     }
 }
 
+/// Scaled by a factor such as a quantity or rate, erroring rather than panicking on overflow
+impl Mul<Decimal> for Money {
+    type Output = Result<Money>;
+
+    fn mul(self, factor: Decimal) -> Result<Money> {
+        self.0
+            .checked_mul(factor)
+            .map(Money)
+            .with_context(|| format!("Overflow multiplying {} by {}", self, factor))
+    }
+}
+
+impl Mul<f64> for Money {
+    type Output = Result<Money>;
+
+    fn mul(self, factor: f64) -> Result<Money> {
+        self * Decimal::from_f64(factor)
+            .with_context(|| format!("Failed to convert {} to Decimal", factor))?
+    }
+}
+
+/// Divided by a scalar, erroring on division by zero or overflow rather than panicking
+impl Div<Decimal> for Money {
+    type Output = Result<Money>;
+
+    fn div(self, divisor: Decimal) -> Result<Money> {
+        if divisor.is_zero() {
+            bail!("Can't divide {} by zero", self);
+        }
+        self.0
+            .checked_div(divisor)
+            .map(Money)
+            .with_context(|| format!("Overflow dividing {} by {}", self, divisor))
+    }
+}
+
+impl Div<f64> for Money {
+    type Output = Result<Money>;
+
+    fn div(self, divisor: f64) -> Result<Money> {
+        self / Decimal::from_f64(divisor)
+            .with_context(|| format!("Failed to convert {} to Decimal", divisor))?
+    }
+}
+
 impl AddAssign for Money {
     fn add_assign(&mut self, other: Self) {
         self.0 += other.0
@@ -85,6 +301,25 @@ mod money_tests {
     use super::*;
     use std::convert::TryInto;
 
+    #[test]
+    fn money_allocate() -> Result<()> {
+        let total: Money = "100.00".parse()?;
+        let thirds = total.allocate(&[Decimal::ONE; 3])?;
+        assert_eq!(
+            thirds.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["$33.34", "$33.33", "$33.33"]
+        );
+
+        let weights = ["0.5", "0.3", "0.2"]
+            .iter()
+            .map(|w| w.parse())
+            .collect::<Result<Vec<Decimal>, _>>()?;
+        let shares = total.allocate(&weights)?;
+        assert_eq!(Money::sum(shares)?, total);
+        assert!(total.allocate(&[]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn money_from_f64() -> Result<()> {
         // less than 2 dp
@@ -103,6 +338,102 @@ mod money_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_try_add_overflow() -> Result<()> {
+        let amounts = vec![Money(Decimal::MAX), Money::try_from(1.00)?];
+        let err = amounts
+            .into_iter()
+            .try_fold(Money::zero(), |mut total, amount| {
+                total.try_add_assign(amount)?;
+                Ok::<_, Error>(total)
+            })
+            .unwrap_err();
+        assert!(
+            err.to_string().starts_with("Overflow adding $1.00"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            Money::try_from(1.50)?.try_add(Money::try_from(2.25)?)?,
+            Money::try_from(3.75)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn money_from_str() -> Result<()> {
+        let m: Money = "500".parse()?;
+        assert_eq!(m.to_string(), "$500.00");
+        let m: Money = "500.5".parse()?;
+        assert_eq!(m.to_string(), "$500.50");
+        // extra precision is kept
+        let m: Money = "500.555".parse()?;
+        assert_eq!(m.to_string(), "$500.555");
+        let m: Money = "-12".parse()?;
+        assert_eq!(m.to_string(), "($12.00)");
+        assert!("five".parse::<Money>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn money_round_with() -> Result<()> {
+        let m: Money = "2.345".parse()?;
+        assert_eq!(m.to_string(), "$2.345");
+        assert_eq!(
+            m.round_with(2, RoundingStrategy::MidpointAwayFromZero)
+                .to_string(),
+            "$2.35"
+        );
+        assert_eq!(
+            m.round_with(2, RoundingStrategy::MidpointNearestEven)
+                .to_string(),
+            "$2.34"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn money_from_accounting_str() -> Result<()> {
+        let m: Money = "($60.50)".parse()?;
+        assert_eq!(m, Money(Decimal::new(-6050, 2)));
+        assert_eq!(m.to_string(), "($60.50)");
+        assert_eq!(m.to_string().parse::<Money>()?, m);
+        assert_eq!("$(60.50)".parse::<Money>()?, m);
+        assert_eq!("(60.50)".parse::<Money>()?, m);
+        assert_eq!("-$60.50".parse::<Money>()?, m);
+        assert_eq!("$1,250.00".parse::<Money>()?.to_string(), "$1250.00");
+        let err = "($60.50".parse::<Money>().unwrap_err();
+        assert!(err.to_string().contains("Unbalanced"), "{}", err);
+        assert!("60.50)".parse::<Money>().is_err());
+        assert_eq!("($-60.50)".parse::<Money>()?, m);
+        assert_eq!("(-$60.50)".parse::<Money>()?, m);
+        assert_eq!("(-60.50)".parse::<Money>()?, m);
+        assert_eq!("$-60.50".parse::<Money>()?, m);
+        assert_eq!("1,234,567.5".parse::<Money>()?.to_string(), "$1234567.50");
+        let err = "$$5".parse::<Money>().unwrap_err();
+        assert!(err.to_string().contains("currency symbol"), "{}", err);
+        assert!("$($5)".parse::<Money>().is_err());
+        assert!("--5".parse::<Money>().is_err());
+        let err = "1,2,3".parse::<Money>().unwrap_err();
+        assert!(err.to_string().contains("thousands"), "{}", err);
+        assert!("1234,567".parse::<Money>().is_err());
+        assert!(",123".parse::<Money>().is_err());
+        assert!("1,23.45".parse::<Money>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_sub_nonneg() -> Result<()> {
+        let ten = Money::try_from(10.00)?;
+        assert_eq!(
+            ten.checked_sub_nonneg(Money::try_from(4.00)?),
+            Some(Money::try_from(6.00)?)
+        );
+        assert_eq!(ten.checked_sub_nonneg(ten), Some(Money::try_from(0.00)?));
+        assert_eq!(ten.checked_sub_nonneg(Money::try_from(10.01)?), None);
+        Ok(())
+    }
+
     #[test]
     fn test_add() -> Result<()> {
         let add = Money::try_from(100.00)? + Money::try_from(100.00)?;
@@ -110,6 +441,51 @@ mod money_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_sign() -> Result<()> {
+        let positive = Money::try_from(1.5)?;
+        assert!(positive.is_positive() && !positive.is_negative());
+        assert_eq!(positive.signum(), 1);
+
+        let negative = -positive;
+        assert!(negative.is_negative() && !negative.is_positive());
+        assert_eq!(negative.signum(), -1);
+
+        let zero = Money::zero();
+        assert!(!zero.is_positive() && !zero.is_negative());
+        assert_eq!(zero.signum(), 0);
+        // negative zero isn't negative either
+        let negative_zero = Money(-Decimal::new(0, 2));
+        assert!(!negative_zero.is_negative());
+        assert_eq!(negative_zero.signum(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn money_sum() -> Result<()> {
+        assert_eq!(Money::sum(Vec::new())?, Money::zero());
+        let amounts = vec!["10.50".parse()?, "-0.50".parse()?, "5".parse()?];
+        assert_eq!(Money::sum(amounts)?.to_string(), "$15.00");
+        let err = Money::sum(vec![Money(Decimal::MAX), "1".parse()?]).unwrap_err();
+        assert!(err.to_string().starts_with("Overflow adding"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn money_mul_div() -> Result<()> {
+        let ten: Money = "10".parse()?;
+        assert_eq!((ten * Decimal::from(3))?.to_string(), "$30.00");
+        assert_eq!((ten * 3.0)?.to_string(), "$30.00");
+        assert_eq!((ten / Decimal::from(4))?.to_string(), "$2.50");
+        assert!((Money(Decimal::MAX) * Decimal::from(2)).is_err());
+        let err = (ten * 8e83).unwrap_err();
+        assert!(err.to_string().contains("Failed to convert"), "{}", err);
+        let err = (ten / Decimal::zero()).unwrap_err();
+        assert!(err.to_string().contains("by zero"), "{}", err);
+        assert!((ten / 0.0).is_err());
+        Ok(())
+    }
+
     #[test]
     #[should_panic(expected = "Failed to convert")]
     #[allow(unused_must_use)]
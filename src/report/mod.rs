@@ -7,10 +7,13 @@ use crate::account::{
     Tag,
     Type::{self, *},
 };
-use crate::journal_entry::JournalAmount;
+use crate::journal_entry::{JournalAmount, RowFormat};
+use crate::money::Money;
 
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Error, Result};
 use async_std::fs;
+use num_traits::Zero;
+use rust_decimal::{Decimal, RoundingStrategy};
 
 use std::{
     borrow::ToOwned,
@@ -25,17 +28,57 @@ pub struct ReportNode {
     pub types: Vec<Type>,
     pub names: Vec<String>,
     pub tags: Vec<Tag>,
+    /// Inclusive range of numeric account codes
+    pub code_range: Option<(u32, u32)>,
+    /// Breakdown of this node. Children are only offered accounts this node matches, so a
+    /// child without its own types, names, tags or code range inherits this node's, and a child
+    /// that specifies them narrows this node's constraints rather than overriding them.
     pub children: Vec<ReportNode>,
     /// Total for all accounts that match this node but not children
     pub total: Total,
+    /// Total the node is expected to render with, checked by `failed_assertions`
+    pub assert: Option<Money>,
 }
 
 /// The names of the accounts and their total balance
 #[derive(Debug, Default, Clone)]
 pub struct Total(pub Vec<String>, pub JournalAmount);
 
+impl Total {
+    /// Add the accounts and amount of `other` to this total
+    pub fn combine(&mut self, other: &Total) {
+        self.0.extend(other.0.iter().cloned());
+        self.1 += other.1;
+    }
+
+    /// Whether no accounts have been totaled
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn amount(&self) -> JournalAmount {
+        self.1
+    }
+
+    pub fn accounts(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Amount as money that is positive on the `sign` side and negative opposite it,
+    /// as every rendering of a report shows it
+    pub fn signed(&self, sign: Sign) -> Money {
+        match sign {
+            Debit => self.1.to_signed_money(),
+            Credit => -self.1.to_signed_money(),
+        }
+    }
+}
+
 type LineItem = (Vec<String>, Sign, Total);
 
+/// Path to a node whose asserted total isn't met, with the asserted and actual totals
+type FailedAssertion = (Vec<String>, Money, Money);
+
 impl ReportNode {
     pub async fn from_file(file: &str) -> Result<Self> {
         let doc = fs::read_to_string(file).await?;
@@ -68,11 +111,14 @@ impl ReportNode {
 
     fn matches(&self, account: &Account) -> bool {
         // account type must match if specified
-        // in addition to matching on name or tags if they are specified
+        // in addition to matching on name, tags, or code range if they are specified
         (self.types.is_empty() || self.types.iter().any(|t| *t == account.acc_type))
-            && ((self.names.is_empty() && self.tags.is_empty())
+            && ((self.names.is_empty() && self.tags.is_empty() && self.code_range.is_none())
                 || (self.names.iter().any(|n| *n == account.name)
-                    || self.tags.iter().any(|t| account.has_tag(t))))
+                    || self.tags.iter().any(|t| account.has_tag(t))
+                    || self
+                        .code_range
+                        .map_or(false, |range| account.code_in(range))))
     }
 
     fn default_sign(&self) -> Sign {
@@ -93,6 +139,15 @@ impl ReportNode {
         self.types.iter().any(|t2| *t2 == t1)
     }
 
+    /// Sign of the node, its default for its types or else the `parent` node's
+    fn sign_under(&self, parent: Option<Sign>) -> Result<Sign> {
+        if self.types.is_empty() {
+            parent.context("No sign for ReportNode")
+        } else {
+            Ok(self.default_sign())
+        }
+    }
+
     pub fn items(&self) -> Result<Vec<LineItem>> {
         Ok(self.items_with(Vec::new(), None)?.collect())
     }
@@ -103,11 +158,7 @@ impl ReportNode {
         sign: Option<Sign>,
     ) -> Result<Box<dyn Iterator<Item = LineItem>>> {
         path.push(self.header.clone());
-        let sign = if self.types.is_empty() {
-            sign.context("No sign for ReportNode")?
-        } else {
-            self.default_sign()
-        };
+        let sign = self.sign_under(sign)?;
         let items = vec![(path.clone(), sign, self.total())];
         let mut other = Vec::new();
         if self.total.1 != JournalAmount::default() && !self.children.is_empty() {
@@ -129,14 +180,152 @@ impl ReportNode {
         ))
     }
 
+    /// Nodes whose total, as rendered, differs from the total asserted in the spec
+    pub fn failed_assertions(&self) -> Result<Vec<FailedAssertion>> {
+        let mut failed = Vec::new();
+        self.failed_assertions_with(Vec::new(), None, &mut failed)?;
+        Ok(failed)
+    }
+
+    fn failed_assertions_with(
+        &self,
+        mut path: Vec<String>,
+        sign: Option<Sign>,
+        failed: &mut Vec<FailedAssertion>,
+    ) -> Result<()> {
+        path.push(self.header.clone());
+        let sign = self.sign_under(sign)?;
+        if let Some(expected) = self.assert {
+            let actual = self.total().signed(sign);
+            if actual != expected {
+                failed.push((path.clone(), expected, actual));
+            }
+        }
+        for node in self.children.iter() {
+            node.failed_assertions_with(path.clone(), Some(sign), failed)?;
+        }
+        Ok(())
+    }
+
+    /// Headers of the sibling nodes that `account` matches more than one of, where it's only
+    /// applied to the first, or empty if the report places it unambiguously
+    pub fn ambiguous_nodes(&self, account: &Account) -> Vec<String> {
+        if !self.matches(account) {
+            return Vec::new();
+        }
+        let matching: Vec<&ReportNode> = self
+            .children
+            .iter()
+            .filter(|node| node.matches(account))
+            .collect();
+        match matching.as_slice() {
+            [] => Vec::new(),
+            [node] => node.ambiguous_nodes(account),
+            nodes => nodes.iter().map(|node| node.header.clone()).collect(),
+        }
+    }
+
+    /// Each of `accounts` applied to the report that matches more than one sibling node, with
+    /// the headers of those nodes. Such an account is only applied to the first of them.
+    pub fn ambiguous_accounts<'a>(
+        &self,
+        accounts: impl IntoIterator<Item = &'a Account>,
+    ) -> Vec<(String, Vec<String>)> {
+        let applied = self.total().0;
+        accounts
+            .into_iter()
+            .filter(|account| applied.contains(&account.name))
+            .map(|account| (account.name.clone(), self.ambiguous_nodes(account)))
+            .filter(|(_, nodes)| !nodes.is_empty())
+            .collect()
+    }
+
+    /// Check that the report total equals the total of the ledger `balances` of the accounts it
+    /// matches, each counted once, so no balance was lost or applied more than once
+    pub fn reconcile<'a>(
+        &self,
+        balances: impl IntoIterator<Item = (&'a Account, &'a JournalAmount)>,
+    ) -> Result<()> {
+        let expected = JournalAmount::sum(
+            balances
+                .into_iter()
+                .filter(|(account, _)| self.matches(account))
+                .map(|(_, balance)| *balance),
+        )?;
+        let total = self.total().1;
+        if !(total - expected).money().is_zero() {
+            bail!(
+                "Report total {} does not reconcile with ledger total {} of matched accounts",
+                total.to_string().trim(),
+                expected.to_string().trim(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Copy of the report showing only `depth` levels, counting this node as the first.
+    /// Nodes at the cutoff take the totals of all nodes beneath them so the report total is
+    /// unchanged.
+    pub fn collapse_below(&self, depth: usize) -> ReportNode {
+        let (children, total) = if depth <= 1 {
+            (Vec::new(), self.total())
+        } else {
+            (
+                self.children
+                    .iter()
+                    .map(|node| node.collapse_below(depth - 1))
+                    .collect(),
+                self.total.clone(),
+            )
+        };
+        ReportNode {
+            header: self.header.clone(),
+            types: self.types.clone(),
+            names: self.names.clone(),
+            tags: self.tags.clone(),
+            code_range: self.code_range,
+            children,
+            total,
+            assert: self.assert,
+        }
+    }
+
+    /// Merge an `overlay` spec into this one. Selectors the overlay specifies replace this
+    /// node's, children with a matching header are merged in turn and new children appended.
+    pub fn merge_overlay(&mut self, overlay: &ReportNode) {
+        if !overlay.types.is_empty() {
+            self.types = overlay.types.clone();
+        }
+        if !overlay.names.is_empty() {
+            self.names = overlay.names.clone();
+        }
+        if !overlay.tags.is_empty() {
+            self.tags = overlay.tags.clone();
+        }
+        if overlay.code_range.is_some() {
+            self.code_range = overlay.code_range;
+        }
+        if overlay.assert.is_some() {
+            self.assert = overlay.assert;
+        }
+        for child in overlay.children.iter() {
+            match self
+                .children
+                .iter_mut()
+                .find(|node| node.header == child.header)
+            {
+                Some(node) => node.merge_overlay(child),
+                None => self.children.push(child.clone()),
+            }
+        }
+    }
+
     /// total of this node and all children
     pub fn total(&self) -> Total {
         self.children
             .iter()
             .fold(self.total.clone(), |mut grand_total, node| {
-                let mut total = node.total();
-                grand_total.0.append(&mut total.0);
-                grand_total.1 += total.1;
+                grand_total.combine(&node.total());
                 grand_total
             })
     }
@@ -169,8 +358,10 @@ impl TryFrom<raw::ReportNode> for ReportNode {
             types,
             names,
             tags,
+            code_range: raw_report.code_range.map(|[min, max]| (min, max)),
             children,
             total: Total(Vec::new(), JournalAmount::default()),
+            assert: raw_report.assert.map(TryInto::try_into).transpose()?,
         })
     }
 }
@@ -188,25 +379,131 @@ impl FromStr for ReportNode {
     }
 }
 
+/// Options controlling how a report is rendered
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Round each displayed total to whole dollars. Totals are summed at full precision and
+    /// only rounded for display so a subtotal may differ from the sum of its rounded children.
+    pub round: bool,
+    /// List the names of the accounts making up each leaf total beneath it
+    pub detail: bool,
+    /// Number of spaces to indent each level by
+    pub indent: usize,
+    /// Draw `├─`/`└─` connectors between each node and its children
+    pub tree: bool,
+    /// Show each node's total as a percentage of its parent's total
+    pub percent_of_parent: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            round: false,
+            detail: false,
+            indent: 2,
+            tree: false,
+            percent_of_parent: false,
+        }
+    }
+}
+
+/// A report paired with the options to render it with
+pub struct ReportDisplay<'a> {
+    node: &'a ReportNode,
+    options: &'a RenderOptions,
+}
+
+impl ReportNode {
+    pub fn display_with<'a>(&'a self, options: &'a RenderOptions) -> ReportDisplay<'a> {
+        ReportDisplay {
+            node: self,
+            options,
+        }
+    }
+}
+
 impl fmt::Display for ReportNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let items = self.items().map_err(|_| std::fmt::Error::default());
-        for item in items?.iter() {
-            let mut indentation = (1..item.0.len()).fold(String::new(), |mut ident, _| {
-                ident.push_str("  ");
-                ident
-            });
+        self.display_with(&RenderOptions::default()).fmt(f)
+    }
+}
+
+impl fmt::Display for ReportDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let items = self.node.items().map_err(|_| std::fmt::Error::default())?;
+        let width = self.options.indent;
+        let totals: Vec<Money> = items
+            .iter()
+            .map(|(_, sign, total)| {
+                let total = total.signed(*sign);
+                if self.options.round {
+                    total.round_with(0, RoundingStrategy::MidpointAwayFromZero)
+                } else {
+                    total
+                }
+            })
+            .collect();
+        // totals of any magnitude line up on their decimal points
+        let format = RowFormat::fit_money(totals.iter().copied());
+        // whether the node at each depth has siblings still to come, for tree connectors
+        let mut continues: Vec<bool> = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let depth = item.0.len() - 1;
+            let mut indentation = if self.options.tree && depth > 0 {
+                let last = items[i + 1..]
+                    .iter()
+                    .find(|next| next.0.len() <= item.0.len())
+                    .map_or(true, |next| next.0.len() < item.0.len());
+                continues.truncate(depth - 1);
+                let mut prefix = continues.iter().fold(String::new(), |mut prefix, more| {
+                    prefix.push(if *more { '│' } else { ' ' });
+                    prefix.push_str(&" ".repeat(width));
+                    prefix
+                });
+                prefix.push(if last { '└' } else { '├' });
+                prefix.push_str(&"─".repeat(width.max(1) - 1));
+                prefix.push(' ');
+                continues.push(!last);
+                prefix
+            } else {
+                " ".repeat(width * depth)
+            };
             let header = item.0.last().map(ToOwned::to_owned).unwrap_or_default();
             indentation.push_str(&header);
             let indented_header = indentation;
             // apply sign to journal ammount
-            let total = match (item.1, item.2 .1) {
-                (Credit, JournalAmount::Credit(money)) => money,
-                (Credit, JournalAmount::Debit(money)) => -money,
-                (Debit, JournalAmount::Debit(money)) => money,
-                (Debit, JournalAmount::Credit(money)) => -money,
-            };
-            writeln!(f, "{:<32}{:>6}", indented_header, total)?;
+            let total = item.2.signed(item.1);
+            let rounded = format.align(totals[i]);
+            // the top level has no parent to be a share of
+            let parent = items[..i]
+                .iter()
+                .rev()
+                .find(|parent| parent.0.len() + 1 == item.0.len() && item.0.starts_with(&parent.0));
+            match parent.map(|(_, sign, total)| total.signed(*sign)) {
+                Some(parent) if self.options.percent_of_parent => {
+                    // no share of a zero total is meaningful
+                    let percent = if parent.0.is_zero() {
+                        "—".to_string()
+                    } else {
+                        format!("{}%", (total.0 / parent.0 * Decimal::from(100)).round())
+                    };
+                    writeln!(f, "{:<32}{:>6}{:>7}", indented_header, rounded, percent)?;
+                }
+                _ => writeln!(f, "{:<32}{:>6}", indented_header, rounded)?,
+            }
+            // a leaf is any item not followed by one nested beneath it
+            let leaf = items
+                .get(i + 1)
+                .map_or(true, |next| !next.0.starts_with(&item.0));
+            if self.options.detail && leaf {
+                let indentation = " ".repeat(width * item.0.len());
+                for account in item.2 .0.iter() {
+                    writeln!(f, "{}- {}", indentation, account)?;
+                }
+            }
+        }
+        if self.options.round {
+            writeln!(f, "(totals rounded to whole dollars)")?;
         }
         Ok(())
     }
@@ -341,6 +638,473 @@ mod report_tests {
             "Matches if name matches even if tags don't match"
         );
 
+        let node = ReportNode {
+            code_range: Some((5000, 5999)),
+            ..Default::default()
+        };
+        let account = Account {
+            code: Some("5100".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            node.matches(&account),
+            "Matches account based on code range"
+        );
+
+        let account = Account {
+            code: Some("6100".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            !node.matches(&account),
+            "Doesn't match if code is outside range"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_rounded() -> Result<()> {
+        let node = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            total: Total(
+                vec!["Operating Expenses".to_string()],
+                JournalAmount::Debit(250.49.try_into()?),
+            ),
+            ..Default::default()
+        };
+        assert!(node.to_string().contains("$250.49"));
+        let options = RenderOptions {
+            round: true,
+            ..Default::default()
+        };
+        let rendered = node.display_with(&options).to_string();
+        assert!(rendered.contains("$250\n"), "{}", rendered);
+        assert!(!rendered.contains("$250.49"));
+        // underlying total is untouched
+        assert_eq!(node.total.1, JournalAmount::Debit(250.49.try_into()?));
+        Ok(())
+    }
+
+    #[test]
+    fn render_detail() -> Result<()> {
+        let node = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            total: Total(
+                vec!["Operating Expenses".to_string()],
+                JournalAmount::Debit(250.00.try_into()?),
+            ),
+            ..Default::default()
+        };
+        assert!(!node.to_string().contains("- Operating Expenses"));
+        let options = RenderOptions {
+            detail: true,
+            ..Default::default()
+        };
+        let rendered = node.display_with(&options).to_string();
+        assert!(
+            rendered.contains("Expenses") && rendered.contains("  - Operating Expenses\n"),
+            "{}",
+            rendered
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn child_inherits_parent_tags() -> Result<()> {
+        let mut report = ReportNode {
+            header: "Current Assets".to_string(),
+            types: vec![Asset],
+            tags: tags!["Current"]?,
+            children: vec![
+                ReportNode {
+                    header: "Bank".to_string(),
+                    tags: tags!["Bank"]?,
+                    ..Default::default()
+                },
+                ReportNode {
+                    header: "Everything Else".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let account = |name: &str, tags: Vec<Tag>| Account {
+            acc_type: Asset,
+            name: name.to_string(),
+            tags,
+            ..Default::default()
+        };
+        let balance = JournalAmount::Debit(100.00.try_into()?);
+        let checking = account("Checking", tags!["Current", "Bank"]?);
+        let savings = account("Savings", tags!["Bank"]?);
+        let inventory = account("Inventory", tags!["Current"]?);
+        let building = account("Building", Vec::new());
+        assert!(report.apply_balance((&checking, &balance))?);
+        assert!(!report.apply_balance((&savings, &balance))?);
+        assert!(report.apply_balance((&inventory, &balance))?);
+        assert!(!report.apply_balance((&building, &balance))?);
+        assert_eq!(report.children[0].total.accounts(), ["Checking"]);
+        // no constraints of its own but still only current accounts
+        assert_eq!(report.children[1].total.accounts(), ["Inventory"]);
+        assert!(report.total.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn render_percent_of_parent() -> Result<()> {
+        let leaf = |header: &str, amount: f64| -> Result<ReportNode> {
+            Ok(ReportNode {
+                header: header.to_string(),
+                types: vec![Expense],
+                total: Total(
+                    vec![header.to_string()],
+                    JournalAmount::Debit(amount.try_into()?),
+                ),
+                ..Default::default()
+            })
+        };
+        let report = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            children: vec![leaf("Rent", 200.00)?, leaf("Supplies", 200.00)?],
+            ..Default::default()
+        };
+        let options = RenderOptions {
+            percent_of_parent: true,
+            ..Default::default()
+        };
+        let rendered = report.display_with(&options).to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert!(lines[0].ends_with("$400.00"), "{}", rendered);
+        assert!(lines[1].starts_with("  Rent"), "{}", rendered);
+        assert!(lines[1].ends_with("$200.00    50%"), "{}", rendered);
+
+        let empty = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            children: vec![leaf("Rent", 0.00)?],
+            ..Default::default()
+        };
+        let rendered = empty.display_with(&options).to_string();
+        assert!(rendered
+            .lines()
+            .nth(1)
+            .map_or(false, |line| line.ends_with('—')));
+        Ok(())
+    }
+
+    #[test]
+    fn collapse_below_depth() -> Result<()> {
+        let leaf = |header: &str, amount: f64| -> Result<ReportNode> {
+            Ok(ReportNode {
+                header: header.to_string(),
+                total: Total(
+                    vec![header.to_string()],
+                    JournalAmount::Debit(amount.try_into()?),
+                ),
+                ..Default::default()
+            })
+        };
+        let report = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            children: vec![
+                ReportNode {
+                    header: "Overhead".to_string(),
+                    children: vec![leaf("Rent", 100.00)?, leaf("Utilities", 25.50)?],
+                    ..Default::default()
+                },
+                leaf("Supplies", 10.00)?,
+            ],
+            ..Default::default()
+        };
+        let collapsed = report.collapse_below(2);
+        assert_eq!(collapsed.children.len(), 2);
+        assert!(collapsed
+            .children
+            .iter()
+            .all(|node| node.children.is_empty()));
+        let overhead = &collapsed.children[0].total;
+        assert_eq!(overhead.accounts(), ["Rent", "Utilities"]);
+        assert_eq!(overhead.amount(), JournalAmount::Debit(125.50.try_into()?));
+        assert_eq!(collapsed.total().amount(), report.total().amount());
+        let rendered = collapsed.to_string();
+        assert!(rendered.contains("  Overhead") && !rendered.contains("Rent"));
+        Ok(())
+    }
+
+    #[test]
+    fn combine_totals() -> Result<()> {
+        let mut total = Total::default();
+        assert!(total.is_empty());
+        total.combine(&Total(
+            vec!["Rent".to_string()],
+            JournalAmount::Debit(100.00.try_into()?),
+        ));
+        total.combine(&Total(
+            vec!["Utilities".to_string(), "Insurance".to_string()],
+            JournalAmount::Debit(50.25.try_into()?),
+        ));
+        assert!(!total.is_empty());
+        assert_eq!(total.accounts(), ["Rent", "Utilities", "Insurance"]);
+        assert_eq!(total.amount(), JournalAmount::Debit(150.25.try_into()?));
+        Ok(())
+    }
+
+    #[test]
+    fn signed_total() -> Result<()> {
+        let total = Total(
+            vec!["Widget Sales".to_string()],
+            JournalAmount::Credit(25.00.try_into()?),
+        );
+        assert_eq!(total.signed(Credit), Money::try_from(25.00)?);
+        // a debit balance on a revenue node opposes its natural sign
+        let refunds = Total(
+            vec!["Widget Sales".to_string()],
+            JournalAmount::Debit(10.00.try_into()?),
+        );
+        assert_eq!(refunds.signed(Credit), Money::try_from(-10.00)?);
+        assert_eq!(refunds.signed(Credit).to_string(), "($10.00)");
+        Ok(())
+    }
+
+    #[test]
+    fn render_tree() -> Result<()> {
+        let node = |header: &str, children: Vec<ReportNode>| ReportNode {
+            header: header.to_string(),
+            children,
+            ..Default::default()
+        };
+        let mut report = node(
+            "Expenses",
+            vec![
+                node(
+                    "Overhead",
+                    vec![node("Rent", vec![]), node("Utilities", vec![])],
+                ),
+                node("Supplies", vec![]),
+            ],
+        );
+        report.types = vec![Expense];
+        let options = RenderOptions {
+            tree: true,
+            ..Default::default()
+        };
+        let rendered = report.display_with(&options).to_string();
+        let headers: Vec<&str> = rendered
+            .lines()
+            // drop the totals after each header
+            .map(|line| line.trim_end_matches(|c: char| !c.is_alphabetic()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                "Expenses",
+                "├─ Overhead",
+                "│  ├─ Rent",
+                "│  └─ Utilities",
+                "└─ Supplies",
+            ],
+            "{}",
+            rendered
+        );
+
+        let options = RenderOptions {
+            indent: 4,
+            ..Default::default()
+        };
+        let rendered = report.display_with(&options).to_string();
+        assert!(rendered.contains("\n        Rent "), "{}", rendered);
+        Ok(())
+    }
+
+    #[test]
+    fn render_aligns_decimal_points() -> Result<()> {
+        let node = |header: &str, amount: f64| -> Result<ReportNode> {
+            Ok(ReportNode {
+                header: header.to_string(),
+                total: Total(Vec::new(), JournalAmount::Debit(amount.try_into()?)),
+                ..Default::default()
+            })
+        };
+        let report = ReportNode {
+            types: vec![Asset],
+            children: vec![node("Petty Cash", 5.0)?, node("Building", 1_250_000.0)?],
+            ..node("Assets", 0.0)?
+        };
+        let rendered = report.to_string();
+        let points: Vec<Option<usize>> = rendered.lines().map(|line| line.find('.')).collect();
+        assert_eq!(points.len(), 3, "{}", rendered);
+        assert!(
+            points.iter().all(|point| *point == points[0]),
+            "{}",
+            rendered
+        );
+        assert!(points[0].is_some(), "{}", rendered);
+        Ok(())
+    }
+
+    #[test]
+    fn asserted_totals() -> Result<()> {
+        let mut report: ReportNode = "
+header: Revenue
+types: [Revenue]
+assert: 150
+breakdown:
+  - header: Sales
+    names: [Sales]
+    assert: 100
+  - header: Services
+    names: [Services]
+    assert: 60
+"
+        .parse()?;
+        let account = |name: &str| Account {
+            acc_type: Revenue,
+            name: name.to_string(),
+            ..Default::default()
+        };
+        report.apply_balance((
+            &account("Sales"),
+            &JournalAmount::Credit(100.00.try_into()?),
+        ))?;
+        report.apply_balance((
+            &account("Services"),
+            &JournalAmount::Credit(50.00.try_into()?),
+        ))?;
+        let failed = report.failed_assertions()?;
+        assert_eq!(
+            failed,
+            vec![(
+                vec!["Revenue".to_string(), "Services".to_string()],
+                "60".parse()?,
+                "50".parse()?
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn merge_overlay_spec() -> Result<()> {
+        let mut base: ReportNode = "
+header: Expenses
+types: [Expense]
+breakdown:
+  - header: Overhead
+    tags: [Indirect]
+    breakdown:
+      - header: Rent
+        names: [Rent]
+  - header: Supplies
+    names: [Supplies]
+"
+        .parse()?;
+        let overlay: ReportNode = "
+header: Expenses
+breakdown:
+  - header: Overhead
+    breakdown:
+      - header: Utilities
+        names: [Electric, Water]
+  - header: Supplies
+    names: [Office Supplies]
+"
+        .parse()?;
+        base.merge_overlay(&overlay);
+        assert_eq!(base.types, vec![Expense]);
+        let headers = |node: &ReportNode| -> Vec<String> {
+            node.children.iter().map(|n| n.header.clone()).collect()
+        };
+        assert_eq!(headers(&base), ["Overhead", "Supplies"]);
+        let overhead = &base.children[0];
+        assert_eq!(overhead.tags, tags!["Indirect"]?);
+        assert_eq!(headers(overhead), ["Rent", "Utilities"]);
+        assert_eq!(overhead.children[1].names, ["Electric", "Water"]);
+        assert_eq!(base.children[1].names, ["Office Supplies"]);
+        Ok(())
+    }
+
+    #[test]
+    fn reconcile_detects_double_counting() -> Result<()> {
+        let account = Account {
+            name: "Operating Expenses".to_string(),
+            acc_type: Expense,
+            tags: tags!["Indirect"]?,
+            ..Default::default()
+        };
+        let balance = JournalAmount::Debit(100.00.try_into()?);
+        let mut report = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            children: vec![
+                ReportNode {
+                    header: "By Name".to_string(),
+                    names: vec!["Operating Expenses".to_string()],
+                    ..Default::default()
+                },
+                ReportNode {
+                    header: "By Tag".to_string(),
+                    tags: tags!["Indirect"]?,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        // overlapping nodes still take the account once, in the first
+        report.apply_balance((&account, &balance))?;
+        report.reconcile(vec![(&account, &balance)])?;
+        assert_eq!(report.total().1, balance);
+
+        report.apply_balance((&account, &balance))?;
+        let err = report.reconcile(vec![(&account, &balance)]).unwrap_err();
+        assert!(err.to_string().contains("does not reconcile"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn ambiguous_sibling_nodes() -> Result<()> {
+        let account = Account {
+            name: "Operating Expenses".to_string(),
+            acc_type: Expense,
+            tags: tags!["Indirect"]?,
+            ..Default::default()
+        };
+        let sibling = |header: &str| -> Result<ReportNode> {
+            Ok(ReportNode {
+                header: header.to_string(),
+                tags: tags!["Indirect"]?,
+                ..Default::default()
+            })
+        };
+        let mut report = ReportNode {
+            header: "Expenses".to_string(),
+            types: vec![Expense],
+            children: vec![ReportNode {
+                header: "Overhead".to_string(),
+                children: vec![sibling("Admin")?, sibling("Facilities")?],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(report.ambiguous_nodes(&account), ["Admin", "Facilities"]);
+
+        let balance = JournalAmount::Debit(100.00.try_into()?);
+        assert!(report.ambiguous_accounts(vec![&account]).is_empty());
+        report.apply_balance((&account, &balance))?;
+        report.reconcile(vec![(&account, &balance)])?;
+        assert_eq!(
+            report.ambiguous_accounts(vec![&account]),
+            [(
+                "Operating Expenses".to_string(),
+                vec!["Admin".to_string(), "Facilities".to_string()]
+            )]
+        );
+
+        report.children[0].children.pop();
+        assert!(report.ambiguous_nodes(&account).is_empty());
         Ok(())
     }
 }
@@ -7,5 +7,8 @@ pub struct ReportNode {
     pub types: Option<Vec<String>>,
     pub names: Option<Vec<String>>,
     pub tags: Option<Vec<String>>,
+    pub code_range: Option<[u32; 2]>,
     pub breakdown: Option<Vec<ReportNode>>,
+    /// Total the node is expected to have
+    pub assert: Option<f64>,
 }
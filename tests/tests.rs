@@ -1,12 +1,13 @@
 use self::JournalAmountTest::*;
-use accounts::account::Type::*;
+use accounts::account::{Account, Tag, Type::*};
 use accounts::chart_of_accounts::ChartOfAccounts;
-use accounts::entry::Entry;
+use accounts::entry::{DateOrder, Entry, EntryFormat};
 use accounts::journal_entry::*;
 use accounts::report::ReportNode;
 use accounts::*;
 use anyhow::Result;
-use futures::stream::TryStreamExt;
+use chrono::NaiveDate;
+use futures::stream::{StreamExt, TryStreamExt};
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::convert::TryInto;
@@ -22,6 +23,18 @@ async fn test_basic_entries() -> Result<()> {
     Ok(())
 }
 
+/// Test entries can be parsed from any reader
+#[async_std::test]
+async fn test_entries_from_reader() -> Result<()> {
+    let doc = std::fs::read_to_string("./tests/fixtures/entries/2020/01.yaml")?;
+    let reader = async_std::io::Cursor::new(doc);
+    let entries = Ledger::entries_from_reader(reader)
+        .try_collect::<Vec<Entry>>()
+        .await?;
+    assert_eq!(dbg!(&entries).len(), 8);
+    Ok(())
+}
+
 /// Test that a dir containing nested dirs parses without error
 #[async_std::test]
 async fn test_nested_dirs() -> Result<()> {
@@ -46,6 +59,18 @@ async fn test_multiple_entries_in_one_file() -> Result<()> {
     Ok(())
 }
 
+/// Test that a file that can't be read is skipped and reported while the rest still parse
+#[async_std::test]
+async fn test_unreadable_file_skipped() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_with_unreadable"));
+    let entries = ledger.entries().try_collect::<Vec<Entry>>().await?;
+    assert_eq!(entries.len(), 2);
+    let skipped = ledger.skipped_files();
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0].0.ends_with("garbage.yaml"));
+    Ok(())
+}
+
 /// Test that journal entries from entries are correct
 #[async_std::test]
 async fn test_journal_from_entries() -> Result<()> {
@@ -150,6 +175,1795 @@ async fn test_balance() -> Result<()> {
     Ok(())
 }
 
+/// Test balance differences between two ledgers differing by one entry
+#[async_std::test]
+async fn test_diff_balances() -> Result<()> {
+    let ledger = Ledger::new(Some(
+        "./tests/fixtures/entries_multiple_entries_in_one_file",
+    ));
+    let other = Ledger::new(Some("./tests/fixtures/entries_nested_dirs/2020/01"));
+    let diff = ledger.diff_balances(&other).await?;
+    assert_eq!(dbg!(&diff).len(), 2);
+    assert_eq!(
+        diff.get("Accounts Payable"),
+        Some(&JournalAmount::Debit(100.00.try_into()?))
+    );
+    assert_eq!(
+        diff.get("Credit Card"),
+        Some(&JournalAmount::Credit(100.00.try_into()?))
+    );
+    Ok(())
+}
+
+/// Test filtering entries and balances to several accounts at once
+#[async_std::test]
+async fn test_account_filter() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let accounts = vec!["Credit Card".to_string(), "Widget Sales".to_string()];
+    let entries = ledger
+        .entries_filtered(accounts.clone())
+        .try_collect::<Vec<Entry>>()
+        .await?;
+    assert_eq!(dbg!(&entries).len(), 4);
+    for entry in entries.iter() {
+        assert!(
+            entry.amount_of_account("Credit Card")?.is_some()
+                || entry.amount_of_account("Widget Sales")?.is_some()
+        );
+    }
+    let balances = ledger.balances_filtered(accounts, None).await?;
+    let mut filtered: Vec<_> = balances.keys().collect();
+    filtered.sort();
+    assert_eq!(filtered, vec!["Credit Card", "Widget Sales"]);
+    assert_eq!(
+        balances.get("Credit Card"),
+        ledger.balances(None).await?.get("Credit Card")
+    );
+    Ok(())
+}
+
+/// Test invoices given by a flat amount with payments attached balance against AP and AR
+#[async_std::test]
+async fn test_amount_only_invoice_with_payment() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_amount_only"));
+    let balances = ledger.balances(None).await?;
+    dbg!(&balances);
+    assert_eq!(balances.get("Accounts Payable"), None);
+    assert_eq!(
+        balances.get("Operating Expenses"),
+        Some(&JournalAmount::Debit(100.00.try_into()?))
+    );
+    assert_eq!(
+        balances.get("Accounts Receivable"),
+        Some(&JournalAmount::Debit(60.00.try_into()?))
+    );
+    assert_eq!(
+        balances.get("Business Checking"),
+        Some(&JournalAmount::Credit(60.00.try_into()?))
+    );
+    let total = balances
+        .values()
+        .fold(JournalAmount::default(), |mut acc, amount| {
+            acc += *amount;
+            acc
+        });
+    assert_eq!(total.money(), money::Money::default());
+
+    // the fully paid bill is left out and the partly paid invoice is still owed the rest
+    let invoices = ledger.invoice_balances().await?;
+    assert_eq!(invoices.len(), 1);
+    assert_eq!(invoices[0].id, "INV-1");
+    assert_eq!(invoices[0].amount, 60.00.try_into()?);
+    Ok(())
+}
+
+/// Test entry dates in other formats parse and unparseable ones name the field and value
+#[async_std::test]
+async fn test_entry_date_formats() -> Result<()> {
+    let entry: Entry = "
+type: Payment Sent
+date: 01/15/2020
+end: 31-01-2020
+party: ACME Business Services
+account: Credit Card
+amount: 100
+"
+    .parse()?;
+    let until = "2020-12-31".parse()?;
+    assert_eq!(
+        entry.dates(until).collect::<Vec<_>>(),
+        vec!["2020-01-15".parse()?]
+    );
+
+    // either order could be meant unless one is given
+    let doc = "
+type: Payment Sent
+date: 03/04/2020
+party: ACME Business Services
+account: Credit Card
+amount: 100
+";
+    let err = doc.parse::<Entry>().unwrap_err();
+    assert!(
+        dbg!(format!("{:#}", err)).contains("Ambiguous date '03/04/2020'"),
+        "{:#}",
+        err
+    );
+    let entry = Entry::parse(doc, Some(DateOrder::DayFirst))?;
+    assert_eq!(
+        entry.dates(until).collect::<Vec<_>>(),
+        vec!["2020-04-03".parse()?]
+    );
+    let entry = Entry::parse(doc, Some(DateOrder::MonthFirst))?;
+    assert_eq!(
+        entry.dates(until).collect::<Vec<_>>(),
+        vec!["2020-03-04".parse()?]
+    );
+
+    let err = Entry::parse(
+        &doc.replace("03/04/2020", "15/01/2020"),
+        Some(DateOrder::MonthFirst),
+    )
+    .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("Invalid date '15/01/2020'"),
+        "{:#}",
+        err
+    );
+    Ok(())
+}
+
+/// Test payables as of a date before a payment versus after it
+#[async_std::test]
+async fn test_payable_until() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_recurring"));
+    let before = ledger.payable(Some("2020-01-01".parse()?)).await?;
+    assert_eq!(
+        dbg!(&before).get("ACME Business Services"),
+        Some(&JournalAmount::Credit(100.00.try_into()?))
+    );
+    let after = ledger.payable(Some("2020-01-02".parse()?)).await?;
+    assert!(dbg!(&after)
+        .get("ACME Business Services")
+        .map_or(false, |amount| amount.money() == money::Money::default()));
+    Ok(())
+}
+
+/// Test the same entry included twice isn't double counted when deduplicating
+#[async_std::test]
+async fn test_dedup_entries() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_duplicated"));
+    let balances = ledger.balances(None).await?;
+    assert_eq!(
+        balances.get("Operating Expenses"),
+        Some(&JournalAmount::Debit(200.00.try_into()?))
+    );
+
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_duplicated")).dedup();
+    let balances = ledger.balances(None).await?;
+    assert_eq!(
+        balances.get("Operating Expenses"),
+        Some(&JournalAmount::Debit(100.00.try_into()?))
+    );
+    assert!(balances.contains_key("Office Supplies"));
+    assert_eq!(
+        balances.get("Credit Card"),
+        Some(&JournalAmount::Credit(8.00.try_into()?))
+    );
+    // only the conflicting duplicate is reported, and not as a skipped file
+    assert!(ledger.skipped_files().is_empty());
+    let collisions = ledger.id_collisions();
+    assert_eq!(dbg!(&collisions).len(), 1);
+    assert_eq!(collisions[0].0, "SUPPLIES-1");
+    assert!(collisions[0].1.ends_with("supplies.yaml"));
+    Ok(())
+}
+
+/// Test rate × quantity items are rounded to cents and the invoice still balances
+#[async_std::test]
+async fn test_item_rounding() -> Result<()> {
+    let entry: Entry = "
+type: Purchase Invoice
+date: 2020-01-05
+party: Office Depot
+account: Office Supplies
+items:
+  - description: Pens
+    quantity: 3
+    rate: 0.333
+payment:
+  account: Business Checking
+  amount: 1
+"
+    .parse()?;
+    assert_eq!(entry.total_with_extras()?, 1.00.try_into()?);
+    let journal_entries = JournalEntry::from_entry(entry, Some("2020-01-05".parse()?))?;
+    Expect(&journal_entries)
+        .contains("2020-01-05", "Office Supplies", Debit(1.00), "Office Depot")
+        .contains(
+            "2020-01-05",
+            "Business Checking",
+            Credit(1.00),
+            "Office Depot",
+        );
+    // payment settles the invoice exactly so nothing is left on AP
+    assert_eq!(journal_entries.len(), 2);
+
+    let entry: Entry = "
+type: Purchase Invoice
+date: 2020-01-05
+party: Office Depot
+account: Office Supplies
+rounding: down
+items:
+  - description: Pens
+    quantity: 3
+    rate: 0.333
+"
+    .parse()?;
+    assert_eq!(entry.total_with_extras()?, 0.99.try_into()?);
+    Ok(())
+}
+
+/// Test revenue is reported both net of sales tax and gross
+#[async_std::test]
+async fn test_revenue_net_and_gross() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_sales_tax"));
+    let revenue = ledger
+        .revenue(
+            Some("2020-01-01".parse()?),
+            Some("2020-12-31".parse()?),
+            "Sales Tax Payable",
+        )
+        .await?;
+    let john = revenue.get("John Smith").expect("revenue for party");
+    assert_eq!(john.net, 100.00.try_into()?);
+    assert_eq!(john.gross(), 108.00.try_into()?);
+    Ok(())
+}
+
+/// Test dates are bucketed into fiscal years starting in July
+#[async_std::test]
+async fn test_fiscal_year() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_sales_tax"));
+    assert_eq!(ledger.fiscal_year("2020-03-15".parse()?), 2020);
+
+    let ledger = ledger.with_fiscal_year_start(7, 1)?;
+    assert_eq!(ledger.fiscal_year("2020-03-15".parse()?), 2020);
+    assert_eq!(ledger.fiscal_year("2020-07-01".parse()?), 2021);
+    assert_eq!(ledger.fiscal_year("2021-03-15".parse()?), 2021);
+    assert_eq!(
+        ledger.fiscal_year_bounds(2021),
+        ("2020-07-01".parse()?, "2021-06-30".parse()?)
+    );
+
+    // both sales fall in the fiscal year ending June 2020
+    let (from, until) = ledger.fiscal_year_bounds(2020);
+    let revenue = ledger
+        .revenue(Some(from), Some(until), "Sales Tax Payable")
+        .await?;
+    assert_eq!(revenue["John Smith"].net, 150.00.try_into()?);
+    assert!(ledger.with_fiscal_year_start(2, 30).is_err());
+    Ok(())
+}
+
+/// Test entries are written back out in a canonical form that re-parses the same
+#[async_std::test]
+async fn test_format_entries() -> Result<()> {
+    let messy = "
+# leading comments are kept
+#
+# but not key order
+items:
+  - amount: 100
+    description:   Business Services
+  - {description: Pens, quantity: 3, rate: 0.333, account: Office Supplies}
+party: ACME Business Services
+account:     Operating Expenses
+date: 01/15/2020
+type: Purchase Invoice
+repeat: monthly
+end: 2020-03-15
+---
+type: Payment Sent
+id: PAY-1
+date: 2020-01-16
+party: ACME Business Services
+account: Credit Card
+amount: 101
+";
+    let formatted = accounts::entry::format_docs(messy)?;
+    println!("{}", formatted);
+    assert!(formatted.starts_with(
+        "---\n# leading comments are kept\n#\n# but not key order\ntype: Purchase Invoice\n\
+        date: 2020-01-15\n"
+    ));
+    assert!(formatted.contains("repeat: monthly\nend: 2020-03-15\n"));
+    assert!(formatted.contains("---\nid: PAY-1\n"));
+    // canonical form is stable and parses to the same entries
+    assert_eq!(accounts::entry::format_docs(&formatted)?, formatted);
+    let until = "2020-12-31".parse()?;
+    let dates = |doc: &str| -> Result<Vec<_>> {
+        doc.split("---\n")
+            .filter(|doc| !doc.trim().is_empty())
+            .map(|doc| Ok(doc.parse::<Entry>()?.dates(until).collect::<Vec<_>>()))
+            .collect()
+    };
+    assert_eq!(dates(&formatted)?, dates(messy)?);
+    let entry: Entry = formatted
+        .split("---\n")
+        .nth(1)
+        .unwrap_or_default()
+        .parse()?;
+    assert_eq!(
+        entry.comments(),
+        ["leading comments are kept", "", "but not key order"]
+    );
+    Ok(())
+}
+
+/// Test an imbalance is spread across several accounts by weight and balances exactly
+#[async_std::test]
+async fn test_balanced_across() -> Result<()> {
+    let date = "2020-01-31".parse()?;
+    let party = Some("ACME Business Services".to_string());
+    let lines = vec![JournalEntry(
+        date,
+        "Overhead".to_string(),
+        JournalAmount::Credit(100.00.try_into()?),
+        party.clone(),
+        None,
+    )];
+    let balanced = JournalEntry::balanced_across(
+        lines.clone(),
+        vec![("Shop".to_string(), 0.7), ("Office".to_string(), 0.3)],
+    )?;
+    Expect(&balanced)
+        .contains("2020-01-31", "Shop", Debit(70.00), "ACME Business Services")
+        .contains(
+            "2020-01-31",
+            "Office",
+            Debit(30.00),
+            "ACME Business Services",
+        );
+
+    // leftover cents go to the largest remainders
+    let balanced = JournalEntry::balanced_across(
+        lines,
+        vec![
+            ("Shop".to_string(), 1.0),
+            ("Office".to_string(), 1.0),
+            ("Warehouse".to_string(), 1.0),
+        ],
+    )?;
+    Expect(&balanced)
+        .contains("2020-01-31", "Shop", Debit(33.34), "ACME Business Services")
+        .contains(
+            "2020-01-31",
+            "Office",
+            Debit(33.33),
+            "ACME Business Services",
+        )
+        .contains(
+            "2020-01-31",
+            "Warehouse",
+            Debit(33.33),
+            "ACME Business Services",
+        );
+    let total = balanced
+        .iter()
+        .fold(JournalAmount::default(), |mut acc, line| {
+            acc += line.2;
+            acc
+        });
+    assert_eq!(total.money().signum(), 0);
+    Ok(())
+}
+
+/// Test a split entry moves exact cent shares out of one account into others
+#[async_std::test]
+async fn test_split_entry() -> Result<()> {
+    let entry: Entry = "
+type: Split
+date: 2020-01-31
+party: ACME Business Services
+from: Overhead
+amount: 100
+to:
+  Shop: 33
+  Office: 33
+  Warehouse: 34
+"
+    .parse()?;
+    let journal_entries = JournalEntry::from_entry(entry, Some("2020-01-31".parse()?))?;
+    assert_eq!(dbg!(&journal_entries).len(), 4);
+    Expect(&journal_entries)
+        .contains(
+            "2020-01-31",
+            "Overhead",
+            Credit(100.00),
+            "ACME Business Services",
+        )
+        .contains("2020-01-31", "Shop", Debit(33.00), "ACME Business Services")
+        .contains(
+            "2020-01-31",
+            "Office",
+            Debit(33.00),
+            "ACME Business Services",
+        )
+        .contains(
+            "2020-01-31",
+            "Warehouse",
+            Debit(34.00),
+            "ACME Business Services",
+        );
+
+    let err = "
+type: Split
+date: 2020-01-31
+party: ACME Business Services
+from: Overhead
+amount: 100
+to:
+  Shop: 50
+  Office: 40
+"
+    .parse::<Entry>()
+    .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("must sum to 100"),
+        "{:#}",
+        err
+    );
+    Ok(())
+}
+
+/// Test occurrences of a recurring entry use the amount scheduled as of their date
+#[async_std::test]
+async fn test_scheduled_amounts() -> Result<()> {
+    let entry: Entry = "
+type: Purchase Invoice
+date: 2020-01-01
+party: Landlord
+account: Rent
+amount: 100
+repeat: monthly
+end: 2020-12-01
+amounts:
+  2020-07-01: 150
+"
+    .parse()?;
+    let journal_entries = JournalEntry::from_entry(entry, Some("2020-12-31".parse()?))?;
+    assert_eq!(dbg!(&journal_entries).len(), 24);
+    Expect(&journal_entries)
+        .contains("2020-01-01", "Rent", Debit(100.00), "Landlord")
+        .contains("2020-06-01", "Rent", Debit(100.00), "Landlord")
+        .contains("2020-07-01", "Rent", Debit(150.00), "Landlord")
+        .contains("2020-12-01", "Accounts Payable", Credit(150.00), "Landlord");
+
+    let err = "
+type: Payment Sent
+date: 2020-01-01
+party: Landlord
+account: Bank Account
+amount: 100
+amounts:
+  2020-07-01: 150
+"
+    .parse::<Entry>()
+    .unwrap_err();
+    assert!(format!("{:#}", err).contains("only allowed on recurring entries"));
+    Ok(())
+}
+
+/// Test journal lines of an entry are grouped and rendered under one header
+#[async_std::test]
+async fn test_journal_grouped() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let groups: Vec<JournalGroup> = ledger
+        .journal_grouped(Vec::new(), None, None)
+        .try_collect()
+        .await?;
+    let lines: Vec<JournalEntry> = ledger.journal(None).try_collect().await?;
+    assert_eq!(
+        groups.iter().map(|group| group.lines.len()).sum::<usize>(),
+        lines.len()
+    );
+    let payment = groups
+        .iter()
+        .find(|group| group.memo.as_deref() == Some("Business Services"))
+        .expect("payment group");
+    assert_eq!(payment.lines.len(), 2);
+    let rendered = payment.to_string();
+    println!("{}", rendered);
+    assert_eq!(rendered.matches("2020-01-02").count(), 1);
+    assert!(rendered.starts_with("2020-01-02 | Business Services\n"));
+    assert!(rendered.contains("\n    Credit Card "));
+    assert!(rendered.contains("\n    Accounts Payable "));
+    Ok(())
+}
+
+/// Test a chart of accounts split across files in a dir loads the same as a single file
+#[async_std::test]
+async fn test_chart_of_accounts_from_dir() -> Result<()> {
+    let chart = ChartOfAccounts::from_dir("./tests/fixtures/chart_of_accounts_dir").await?;
+    let single = ChartOfAccounts::from_file("./tests/fixtures/ChartOfAccounts.yaml").await?;
+    assert_eq!(chart.accounts().len(), single.accounts().len());
+    for account in single.accounts() {
+        assert_eq!(chart.get(&account.name)?.acc_type, account.acc_type);
+    }
+
+    // the single file repeats accounts already in the dir and another can't be parsed
+    let dir = unique_temp_dir("accounts_duplicate_chart");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::copy(
+        "./tests/fixtures/ChartOfAccounts.yaml",
+        dir.join("ChartOfAccounts.yaml"),
+    )?;
+    std::fs::copy(
+        "./tests/fixtures/chart_of_accounts_dir/revenue_expenses.yaml",
+        dir.join("revenue_expenses.yaml"),
+    )?;
+    std::fs::write(dir.join("malformed.yaml"), "---\nname: Petty Cash\n")?;
+    let err = ChartOfAccounts::from_dir(&dir.to_string_lossy())
+        .await
+        .unwrap_err()
+        .to_string();
+    std::fs::remove_dir_all(&dir)?;
+    assert!(err.contains("malformed.yaml"), "{}", err);
+    assert!(err.matches("defined more than once").count() > 1, "{}", err);
+    Ok(())
+}
+
+/// Test reading only the entries with a given reference number
+#[async_std::test]
+async fn test_reference_filter() -> Result<()> {
+    let entry: Entry = "
+type: Sales Invoice
+number: INV-001
+date: 2020-01-05
+party: Widget Co
+account: Widget Sales
+amount: 200
+"
+    .parse()?;
+    assert_eq!(entry.reference().as_deref(), Some("INV-001"));
+    assert!(entry.to_yaml()?.contains("number: INV-001\n"));
+
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_numbered")).with_reference("INV-001");
+    let entries: Vec<Entry> = ledger.entries().try_collect().await?;
+    assert_eq!(entries.len(), 2);
+    let journal_entries: Vec<JournalEntry> = ledger.journal(None).try_collect().await?;
+    assert_eq!(dbg!(&journal_entries).len(), 4);
+    Expect(&journal_entries)
+        .contains("2020-01-05", "Widget Sales", Credit(200.00), "Widget Co")
+        .contains(
+            "2020-01-25",
+            "Business Checking",
+            Debit(200.00),
+            "Widget Co",
+        );
+    assert!(journal_entries
+        .iter()
+        .all(|line| line.4.as_deref() == Some("INV-001")));
+    assert!(journal_entries[0].to_string().ends_with(" | #INV-001"));
+    let groups: Vec<JournalGroup> = ledger
+        .journal_grouped(Vec::new(), None, None)
+        .try_collect()
+        .await?;
+    assert!(groups[0].to_string().contains("| #INV-001\n"));
+    Ok(())
+}
+
+/// Test accounts with a balance opposite their normal side are flagged
+#[async_std::test]
+async fn test_abnormal_balances() -> Result<()> {
+    let chart = ChartOfAccounts::from_file("./tests/fixtures/ChartOfAccounts.yaml").await?;
+    let balances: HashMap<String, JournalAmount> = vec![
+        (
+            "Business Checking".to_string(),
+            JournalAmount::Credit(50.00.try_into()?),
+        ),
+        (
+            "Credit Card".to_string(),
+            JournalAmount::Credit(100.00.try_into()?),
+        ),
+        (
+            "Operating Expenses".to_string(),
+            JournalAmount::Debit(150.00.try_into()?),
+        ),
+        ("Accounts Payable".to_string(), JournalAmount::default()),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(
+        chart.abnormal_balances(balances.iter())?,
+        vec!["Business Checking"]
+    );
+    Ok(())
+}
+
+/// Test entries imported from a CSV spreadsheet match their YAML equivalent
+#[async_std::test]
+async fn test_entries_from_csv() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_csv/expenses.csv"))
+        .with_format(EntryFormat::Csv);
+    let entries: Vec<Entry> = ledger.entries().try_collect().await?;
+    let doc = std::fs::read_to_string("./tests/fixtures/entries_csv/expenses.yaml")?;
+    let expected: Vec<Entry> = Ledger::entries_from_reader(async_std::io::Cursor::new(doc))
+        .try_collect()
+        .await?;
+    assert_eq!(entries.len(), 4);
+    for (entry, expected) in entries.iter().zip(expected.iter()) {
+        assert_eq!(entry.to_yaml()?, expected.to_yaml()?);
+    }
+    assert_eq!(entries[2].memo().as_deref(), Some("Widgets, \"deluxe\""));
+    let balances = ledger.balances(None).await?;
+    assert_eq!(
+        balances.get("Widget Sales"),
+        Some(&JournalAmount::Credit(49.99.try_into()?))
+    );
+
+    // rows parse one at a time so an earlier row is read before a later one fails
+    let csv = "date,type,party,account,amount\n\
+        2020-01-01,Payment Sent,ACME,Credit Card,10\n\
+        2020-01-02,Payment Sent,ACME,Credit Card,ten\n";
+    let mut rows = Box::pin(Ledger::entries_from_csv(csv.as_bytes(), None));
+    assert!(rows.next().await.transpose()?.is_some());
+    assert!(rows.next().await.transpose().is_err());
+
+    // the extension alone doesn't select CSV
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_csv/expenses.csv"));
+    assert!(ledger.entries().try_collect::<Vec<_>>().await.is_err());
+    Ok(())
+}
+
+/// Test the lines of an entry net to zero and an unbalanced entry reports the difference
+#[async_std::test]
+async fn test_assert_balanced() -> Result<()> {
+    let entry: Entry = "
+type: Sales Invoice
+date: 2020-01-07
+party: John Smith
+account: Widget Sales
+amount: 100
+"
+    .parse()?;
+    let mut group = JournalGroup::from_entry(&entry, Some("2020-01-31".parse()?))?
+        .pop()
+        .expect("single occurrence");
+    assert_eq!(group.total()?, JournalAmount::default());
+    group.assert_balanced()?;
+
+    group.lines.pop();
+    assert_eq!(group.total()?, JournalAmount::Credit(100.00.try_into()?));
+    let err = group.assert_balanced().unwrap_err();
+    assert!(
+        err.to_string()
+            .ends_with("out of balance by a credit of $100.00"),
+        "{}",
+        err
+    );
+    Ok(())
+}
+
+/// Test the date span of a ledger covers occurrences of recurring entries
+#[async_std::test]
+async fn test_date_span() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_recurring"));
+    assert_eq!(
+        ledger.date_span().await?,
+        Some(("2020-01-01".parse()?, "2020-03-02".parse()?))
+    );
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_csv/expenses.csv"))
+        .with_format(EntryFormat::Csv);
+    assert_eq!(
+        ledger.date_span().await?,
+        Some(("2020-01-01".parse()?, "2020-01-09".parse()?))
+    );
+
+    // rules that end count to their last date however far ahead, those that don't up to today
+    let dir = unique_temp_dir("accounts_date_span");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("monthly.yaml"),
+        "type: Payment Sent
+date: 2020-01-01
+party: Landlord
+account: Rent
+amount: 100
+repeat: monthly
+---
+type: Payment Sent
+date: 2099-01-01
+party: Landlord
+account: Rent
+amount: 100
+repeat: monthly
+end: 2099-03-01
+",
+    )?;
+    std::fs::write(
+        dir.join("count.yaml"),
+        "type: Payment Sent
+date: 2099-05-01
+party: Landlord
+account: Rent
+amount: 100
+repeat: |
+  DTSTART:20990501T000000Z
+  RRULE:FREQ=MONTHLY;COUNT=2
+",
+    )?;
+    let ledger = Ledger::new(Some(&dir.to_string_lossy()));
+    let with_count = ledger.date_span().await;
+    std::fs::remove_file(dir.join("count.yaml"))?;
+    let with_end = ledger.date_span().await;
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(
+        with_count?,
+        Some(("2020-01-01".parse()?, "2099-06-01".parse()?))
+    );
+    assert_eq!(
+        with_end?,
+        Some(("2020-01-01".parse()?, "2099-03-01".parse()?))
+    );
+
+    let dir = std::env::temp_dir().join("accounts_empty_ledger");
+    std::fs::create_dir_all(&dir)?;
+    let ledger = Ledger::new(Some(&dir.to_string_lossy()));
+    let span = ledger.date_span().await;
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(span?, None);
+    Ok(())
+}
+
+/// Test a zero amount renders as a dash in both columns only when asked
+#[async_std::test]
+async fn test_zero_dash() -> Result<()> {
+    let zero = JournalAmount::default();
+    let plain = zero.to_row_string(&RowFormat::default());
+    assert!(plain.contains("$0") && !plain.contains('—'), "{}", plain);
+    let format = RowFormat {
+        zero_dash: true,
+        ..RowFormat::default()
+    };
+    let dashed = zero.to_row_string(&format);
+    assert_eq!(dashed, format!("{:>12} | {:>12}", "—", "—"));
+    let debit = JournalAmount::Debit(1.00.try_into()?).to_row_string(&format);
+    assert!(debit.contains("$1.00"), "{}", debit);
+    Ok(())
+}
+
+/// Test the standard statements built from the chart balance and agree on net income
+#[async_std::test]
+async fn test_standard_statements() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let chart = ChartOfAccounts::from_file("./tests/fixtures/ChartOfAccounts.yaml").await?;
+    let until = Some("2020-12-31".parse()?);
+
+    let balance_sheet = ledger.balance_sheet(&chart, until).await?;
+    println!("{}", balance_sheet);
+    assert_eq!(balance_sheet.total().amount(), JournalAmount::default());
+
+    let net_income = ledger.net_income(&chart, None, until).await?;
+    assert_eq!(net_income, "-225".parse()?);
+    let income_statement = ledger.income_statement(&chart, None, until).await?;
+    println!("{}", income_statement);
+    assert_eq!(
+        income_statement.total().amount(),
+        JournalAmount::from_signed_money(-net_income)
+    );
+    let retained = &balance_sheet.children[2].children[0];
+    assert_eq!(retained.total().amount(), income_statement.total().amount());
+
+    // nothing was earned or spent after the last entry
+    let later = ledger
+        .net_income(&chart, Some("2020-02-01".parse()?), until)
+        .await?;
+    assert_eq!(later, "0".parse()?);
+    Ok(())
+}
+
+/// Test an account that dips negative is flagged even though it ends positive
+#[async_std::test]
+async fn test_negative_dips() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_clearing"));
+    let chart = ChartOfAccounts::new(vec![
+        Account::new(Asset, "Clearing", tags!["clearing"]?),
+        Account::new(Asset, "Business Checking", tags!["clearing"]?),
+        Account::new(Liability, "Accounts Payable", vec![]),
+        Account::new(Asset, "Accounts Receivable", vec![]),
+    ]);
+    let balances = ledger.balances(None).await?;
+    assert_eq!(
+        balances.get("Clearing"),
+        Some(&JournalAmount::Debit(50.00.try_into()?))
+    );
+    let dips = ledger.negative_dips(&chart, &Tag::new("clearing")?).await?;
+    assert_eq!(
+        dips.into_iter().collect::<Vec<_>>(),
+        vec![("Clearing".to_string(), "2020-01-01".parse()?)]
+    );
+    Ok(())
+}
+
+/// Test only the occurrences of a recurring entry inside a window are listed
+#[async_std::test]
+async fn test_occurrences_between() -> Result<()> {
+    let entry: Entry = "
+type: Purchase Invoice
+date: 2020-01-15
+party: Landlord
+account: Rent
+amount: 100
+repeat: monthly
+"
+    .parse()?;
+    let dates = |dates: &[&str]| -> Result<Vec<NaiveDate>> {
+        dates.iter().map(|date| Ok(date.parse()?)).collect()
+    };
+    assert_eq!(
+        entry.occurrences_between("2020-05-01".parse()?, "2020-08-15".parse()?),
+        dates(&["2020-05-15", "2020-06-15", "2020-07-15", "2020-08-15"])?
+    );
+    assert!(entry
+        .occurrences_between("2020-05-16".parse()?, "2020-06-14".parse()?)
+        .is_empty());
+    Ok(())
+}
+
+/// Test the totals of each class of account reconcile
+#[async_std::test]
+async fn test_class_totals() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let chart = ChartOfAccounts::from_file("./tests/fixtures/ChartOfAccounts.yaml").await?;
+    let balances = ledger.balances(None).await?;
+    let totals = chart.class_totals(balances.iter())?;
+    let classes: Vec<_> = totals.iter().map(|(class, _)| class.to_string()).collect();
+    assert_eq!(
+        classes,
+        ["Asset", "Liability", "Equity", "Revenue", "Expense"]
+    );
+    // debits positive, credits negative
+    let signed = |i: usize| totals[i].1.to_signed_money();
+    assert_eq!(signed(4), "250".parse()?);
+    assert_eq!(signed(3), "-25".parse()?);
+    // assets = liabilities + equity + (revenue - expenses) with those on the credit side
+    assert_eq!(
+        signed(0),
+        -signed(1) + -signed(2) + (-signed(3) - signed(4))
+    );
+    Ok(())
+}
+
+/// Test balances over a date window for a party, counting recurring occurrences in the window
+#[async_std::test]
+async fn test_balances_between_filtered() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let balances = ledger
+        .balances_between(
+            Some("2020-01-03".parse()?),
+            Some("2020-01-07".parse()?),
+            Vec::new(),
+            Some("John Smith".to_string()),
+        )
+        .await?;
+    assert_eq!(
+        dbg!(&balances).get("Widget Sales"),
+        Some(&JournalAmount::Credit(15.00.try_into()?))
+    );
+    assert_eq!(
+        balances.get("Business Checking"),
+        Some(&JournalAmount::Debit(15.00.try_into()?))
+    );
+    assert_eq!(balances.get("Operating Expenses"), None);
+
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_recurring"));
+    let balances = ledger
+        .balances_between(
+            Some("2020-01-15".parse()?),
+            Some("2020-02-15".parse()?),
+            vec!["Operating Expenses".to_string()],
+            Some("ACME Business Services".to_string()),
+        )
+        .await?;
+    let accounts: Vec<_> = balances.keys().collect();
+    assert_eq!(accounts, vec!["Operating Expenses"]);
+    assert_eq!(
+        balances.get("Operating Expenses"),
+        Some(&JournalAmount::Debit(100.00.try_into()?))
+    );
+    Ok(())
+}
+
+/// Test an account's amount is summed over each occurrence of a recurring entry
+#[async_std::test]
+async fn test_amount_of_account_through() -> Result<()> {
+    let entry: Entry = "
+type: Purchase Invoice
+date: 2020-01-15
+party: Landlord
+account: Rent
+amount: 100
+repeat: monthly
+end: 2020-03-15
+"
+    .parse()?;
+    assert_eq!(
+        entry.amount_of_account("Rent")?,
+        Some(JournalAmount::Debit(100.00.try_into()?))
+    );
+    assert_eq!(
+        entry.amount_of_account_through("Rent", "2020-12-31".parse()?)?,
+        Some(JournalAmount::Debit(300.00.try_into()?))
+    );
+    assert_eq!(
+        entry.amount_of_account_through("Rent", "2020-02-14".parse()?)?,
+        Some(JournalAmount::Debit(100.00.try_into()?))
+    );
+    assert_eq!(
+        entry.amount_of_account_through("Rent", "2020-01-14".parse()?)?,
+        None
+    );
+    Ok(())
+}
+
+/// Test a ledger whose balances don't net to zero is reported with the amount it's out by
+#[async_std::test]
+async fn test_ledger_balanced() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    assert!(ledger.is_balanced().await?);
+
+    // entries always journal balanced lines, so unbalance the balances directly
+    let mut balances = ledger.balances(None).await?;
+    balances.insert(
+        "Suspense".to_string(),
+        JournalAmount::Debit(12.34.try_into()?),
+    );
+    let err = Ledger::assert_balanced(&balances).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Ledger is out of balance by a debit of $12.34"
+    );
+    Ok(())
+}
+
+/// Test an invoice amount opted in as a checksum must equal the total of its items
+#[async_std::test]
+async fn test_invoice_amount_checksum() -> Result<()> {
+    let invoice = |amount: &str, checksum: bool| -> Result<Entry> {
+        format!(
+            "
+type: Purchase Invoice
+date: 2020-01-01
+party: ACME Business Services
+account: Operating Expenses
+amount: {}
+checksum: {}
+items:
+  - description: Business Services
+    amount: 100
+  - description: Supplies
+    quantity: 3
+    rate: 16.50
+",
+            amount, checksum
+        )
+        .parse()
+    };
+    let entry = invoice("149.50", true)?;
+    assert_eq!(
+        entry.amount_of_account("Operating Expenses")?,
+        Some(JournalAmount::Debit(149.50.try_into()?))
+    );
+    let err = invoice("150", true).unwrap_err();
+    assert!(
+        format!("{:#}", err).contains(
+            "Invoice amount $150.00 doesn't match its items total $149.50, a difference of $0.50"
+        ),
+        "{:#}",
+        err
+    );
+    // without opting in both are still rejected
+    assert!(invoice("149.50", false).is_err());
+    Ok(())
+}
+
+/// Test a snapshot of opening balances plus the entries after it gives the full history's balances
+#[async_std::test]
+async fn test_snapshot_opening() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let cutoff: NaiveDate = "2020-01-04".parse()?;
+    let snapshot = ledger.snapshot_opening(cutoff, "Owner's Equity").await?;
+    assert_eq!(snapshot.body().type_name(), "General Journal");
+    // as it would be saved and read back
+    let snapshot: Entry = snapshot.to_yaml()?.parse()?;
+    let opening = JournalEntry::from_entry(snapshot, None)?;
+    // payables are carried by party
+    Expect(&opening).contains(
+        "2020-01-04",
+        "Accounts Payable",
+        Credit(100.00),
+        "ACME Business Services",
+    );
+    // other balances have no party, debits and credits kept on their side
+    let unassigned = |account: &str| {
+        opening
+            .iter()
+            .filter(|line| line.1 == account && line.3.is_none())
+            .map(|line| line.2)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        unassigned("Operating Expenses"),
+        vec![JournalAmount::Debit(250.00.try_into()?)]
+    );
+    assert_eq!(
+        unassigned("Credit Card"),
+        vec![JournalAmount::Credit(100.00.try_into()?)]
+    );
+    assert_eq!(unassigned("Accounts Payable"), Vec::new());
+    assert!(opening
+        .iter()
+        .all(|line| line.3.as_deref() != Some("Opening Balances")));
+    let mut balances = ledger
+        .balances_between(Some(cutoff.succ()), None, Vec::new(), None)
+        .await?;
+    for JournalEntry(_, account, amount, _, _) in opening {
+        balances
+            .entry(account)
+            .or_default()
+            .try_add_assign(amount)?;
+    }
+    let full = ledger.balances(None).await?;
+    let signed = |balances: &HashMap<String, JournalAmount>| -> Vec<(String, money::Money)> {
+        balances
+            .iter()
+            .map(|(account, balance)| (account.clone(), balance.to_signed_money()))
+            .filter(|(_, amount)| amount.signum() != 0)
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .collect()
+    };
+    assert_eq!(signed(&balances), signed(&full));
+
+    // saved on its own it carries the payable of the real party and no invoice
+    let dir = unique_temp_dir("accounts_snapshot_opening");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join("opening.yaml"),
+        ledger
+            .snapshot_opening(cutoff, "Owner's Equity")
+            .await?
+            .to_yaml()?,
+    )?;
+    let opened = Ledger::new(Some(&dir.to_string_lossy()));
+    let payable = opened.payable(None).await;
+    let parties = opened.party_balances(None).await;
+    let invoices = opened.invoice_balances().await;
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(
+        payable?.into_iter().collect::<Vec<_>>(),
+        vec![(
+            "ACME Business Services".to_string(),
+            JournalAmount::Credit(100.00.try_into()?)
+        )]
+    );
+    assert!(!parties?.contains_key("Opening Balances"));
+    assert!(invoices?.is_empty());
+
+    let unbalanced = "
+type: General Journal
+date: 2020-01-04
+lines:
+  - account: Operating Expenses
+    amount: 250
+  - account: Credit Card
+    amount: -200
+"
+    .parse::<Entry>();
+    assert!(format!("{:#}", unbalanced.unwrap_err()).contains("must net to zero, not"));
+    Ok(())
+}
+
+/// Test the account filter accepts globs and regexes as well as exact names
+#[async_std::test]
+async fn test_account_pattern_filter() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_banks"));
+    for pattern in ["Bank*", "re:^Bank (Checking|Savings)$"] {
+        let entries = ledger
+            .entries_filtered(vec![pattern.to_string()])
+            .try_collect::<Vec<Entry>>()
+            .await?;
+        assert_eq!(entries.len(), 2, "{}", pattern);
+        assert!(entries[0].amount_of_account("Bank Checking")?.is_some());
+        assert!(entries[1].amount_of_account("Bank Savings")?.is_some());
+
+        let balances = ledger
+            .balances_filtered(vec![pattern.to_string()], None)
+            .await?;
+        let accounts: Vec<_> = balances.keys().sorted().collect();
+        assert_eq!(accounts, ["Bank Checking", "Bank Savings"]);
+    }
+    let entries = ledger
+        .entries_filtered(vec!["Bank".to_string()])
+        .try_collect::<Vec<Entry>>()
+        .await?;
+    assert!(entries.is_empty(), "plain names match exactly");
+    assert!(ledger
+        .balances_filtered(vec!["re:(".to_string()], None)
+        .await
+        .is_err());
+    Ok(())
+}
+
+/// Test each entry is paired with the file it was read from
+#[async_std::test]
+async fn test_entries_with_source() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_nested_dirs"));
+    let sourced = ledger
+        .entries_with_source()
+        .try_collect::<Vec<(String, Entry)>>()
+        .await?;
+    let entries = ledger.entries().try_collect::<Vec<Entry>>().await?;
+    assert_eq!(sourced.len(), entries.len());
+    for (source, entry) in sourced.iter() {
+        assert!(std::path::Path::new(source).is_file(), "{}", source);
+        let content = std::fs::read_to_string(source)?;
+        assert!(
+            content.contains(&format!("type: {}", entry.body().type_name())),
+            "{} not from {}",
+            entry.id(),
+            source
+        );
+    }
+    Ok(())
+}
+
+/// Test a discount given as a negative item or extra is debited and reduces what's receivable
+#[async_std::test]
+async fn test_invoice_discount() -> Result<()> {
+    let entry: Entry = "
+type: Sales Invoice
+date: 2020-01-10
+party: John Smith
+account: Widget Sales
+items:
+  - description: Widgets
+    amount: 100
+  - description: Loyalty discount
+    account: Sales Discounts
+    amount: -10
+"
+    .parse()?;
+    let journal = JournalEntry::from_entry(entry, None)?;
+    Expect(&journal)
+        .contains("2020-01-10", "Widget Sales", Credit(100.00), "John Smith")
+        .contains("2020-01-10", "Sales Discounts", Debit(10.00), "John Smith")
+        .contains(
+            "2020-01-10",
+            "Accounts Receivable",
+            Debit(90.00),
+            "John Smith",
+        );
+    assert_eq!(journal.len(), 3);
+
+    let entry: Entry = "
+type: Sales Invoice
+date: 2020-01-10
+party: John Smith
+account: Widget Sales
+items:
+  - description: Widgets
+    amount: 100
+extras:
+  - description: Loyalty discount
+    account: Sales Discounts
+    rate: -0.1
+"
+    .parse()?;
+    Expect(&JournalEntry::from_entry(entry, None)?)
+        .contains("2020-01-10", "Widget Sales", Credit(100.00), "John Smith")
+        .contains("2020-01-10", "Sales Discounts", Debit(10.00), "John Smith")
+        .contains(
+            "2020-01-10",
+            "Accounts Receivable",
+            Debit(90.00),
+            "John Smith",
+        );
+    Ok(())
+}
+
+/// Test a payment split across several accounts journals each and settles the total
+#[async_std::test]
+async fn test_split_payment() -> Result<()> {
+    let entry: Entry = "
+type: Payment Sent
+date: 2020-01-10
+party: Acme Supplies
+payment:
+  - account: Business Checking
+    amount: 60
+  - account: Credit Card
+    amount: 40
+"
+    .parse()?;
+    let yaml = serde_yaml::to_string(&entry)?;
+    let journal = JournalEntry::from_entry(entry, None)?;
+    Expect(&journal)
+        .contains(
+            "2020-01-10",
+            "Business Checking",
+            Credit(60.00),
+            "Acme Supplies",
+        )
+        .contains("2020-01-10", "Credit Card", Credit(40.00), "Acme Supplies")
+        .contains(
+            "2020-01-10",
+            "Accounts Payable",
+            Debit(100.00),
+            "Acme Supplies",
+        );
+    assert_eq!(journal.len(), 3);
+    let reparsed: Entry = yaml.parse()?;
+    assert_eq!(JournalEntry::from_entry(reparsed, None)?.len(), 3);
+
+    let mismatched: Result<Entry> = "
+type: Payment Sent
+date: 2020-01-10
+party: Acme Supplies
+amount: 90
+payment:
+  - account: Business Checking
+    amount: 60
+  - account: Credit Card
+    amount: 40
+"
+    .parse();
+    assert!(mismatched.is_err());
+
+    let entry: Entry = "
+type: Purchase Invoice
+date: 2020-01-10
+party: Acme Supplies
+account: Supplies Expense
+amount: 120
+payment:
+  - account: Business Checking
+    amount: 60
+  - account: Credit Card
+    amount: 40
+"
+    .parse()?;
+    Expect(&JournalEntry::from_entry(entry, None)?)
+        .contains(
+            "2020-01-10",
+            "Supplies Expense",
+            Debit(120.00),
+            "Acme Supplies",
+        )
+        .contains(
+            "2020-01-10",
+            "Business Checking",
+            Credit(60.00),
+            "Acme Supplies",
+        )
+        .contains("2020-01-10", "Credit Card", Credit(40.00), "Acme Supplies")
+        .contains(
+            "2020-01-10",
+            "Accounts Payable",
+            Credit(20.00),
+            "Acme Supplies",
+        );
+    Ok(())
+}
+
+/// Test an account alias, such as a bank's code, resolves to its account
+#[async_std::test]
+async fn test_resolve_alias() -> Result<()> {
+    let checking: Account = "
+name: Bank Checking
+type: Asset
+aliases: [XX00]
+"
+    .parse()?;
+    let chart = ChartOfAccounts::new(vec![
+        checking,
+        Account::new(Liability, "Accounts Payable", vec![]),
+    ]);
+    assert_eq!(
+        chart.resolve("XX00").map(|a| a.name.as_str()),
+        Some("Bank Checking")
+    );
+    assert_eq!(
+        chart.resolve("Bank Checking").map(|a| a.name.as_str()),
+        Some("Bank Checking")
+    );
+    assert!(chart.resolve("XX99").is_none());
+    assert!(chart.to_yaml()?.contains("aliases:\n  - XX00"));
+    Ok(())
+}
+
+/// Test the largest balance is found by absolute amount whichever side it's on
+#[async_std::test]
+async fn test_largest_balance() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let mut balances = ledger.balances(None).await?;
+    assert_eq!(
+        Ledger::largest(&balances),
+        Some((
+            &"Operating Expenses".to_string(),
+            &JournalAmount::Debit(250.00.try_into()?)
+        ))
+    );
+
+    balances.insert(
+        "Owner Capital".to_string(),
+        JournalAmount::Credit(300.00.try_into()?),
+    );
+    assert_eq!(
+        Ledger::largest(&balances).map(|(name, _)| name.as_str()),
+        Some("Owner Capital")
+    );
+    assert_eq!(Ledger::largest(&Default::default()), None);
+    Ok(())
+}
+
+/// Test recurring entries only journal their first occurrence when recurrence is turned off
+#[async_std::test]
+async fn test_no_recurring() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_recurring")).no_recurring();
+    let journal: Vec<JournalEntry> = ledger.journal(None).try_collect().await?;
+    let expense_dates: Vec<String> = journal
+        .iter()
+        .filter(|line| line.1 == "Operating Expenses")
+        .map(|line| line.0.to_string())
+        .collect();
+    assert_eq!(expense_dates, vec!["2020-01-01", "2020-03-01"]);
+
+    let balances = ledger.balances(None).await?;
+    assert_eq!(
+        balances.get("Operating Expenses"),
+        Some(&JournalAmount::Debit(250.00.try_into()?))
+    );
+    Ok(())
+}
+
+/// Test payables and receivables of each party are found together
+#[async_std::test]
+async fn test_party_balances() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let balances = ledger.party_balances(None).await?;
+    let (payable, receivable) = balances["ACME Business Services"];
+    assert_eq!(payable, JournalAmount::Credit(100.00.try_into()?));
+    assert_eq!(receivable.money(), money::Money::default());
+    let (payable, receivable) = balances["John Smith"];
+    assert_eq!(payable.money(), money::Money::default());
+    assert_eq!(receivable, JournalAmount::Debit(10.00.try_into()?));
+
+    let payables = ledger.payable(None).await?;
+    let receivables = ledger.receivable(None).await?;
+    for (party, (payable, receivable)) in balances {
+        assert_eq!(
+            payables
+                .get(&party)
+                .map_or(money::Money::default(), |p| p.money()),
+            payable.money()
+        );
+        assert_eq!(
+            receivables
+                .get(&party)
+                .map_or(money::Money::default(), |r| r.money()),
+            receivable.money()
+        );
+    }
+    Ok(())
+}
+
+/// Test a recurring entry whose rule starts in a zone other than UTC occurs on its local dates
+#[async_std::test]
+async fn test_rrule_local_dates() -> Result<()> {
+    let dates = |repeat: &str| -> Result<Vec<String>> {
+        let entry: Entry = format!(
+            "
+type: Purchase Invoice
+date: 2020-01-01
+party: Landlord
+account: Rent
+amount: 100
+repeat: |
+  {}
+",
+            repeat.replace('\n', "\n  ")
+        )
+        .parse()?;
+        Ok(entry
+            .dates("2020-12-31".parse()?)
+            .map(|date| date.to_string())
+            .collect())
+    };
+    // 23:00 in New York is already the next day in UTC
+    assert_eq!(
+        dates("DTSTART;TZID=America/New_York:20200101T230000\nRRULE:FREQ=MONTHLY;COUNT=3")?,
+        vec!["2020-01-01", "2020-02-01", "2020-03-01"]
+    );
+    // 01:00 in Auckland is still the previous day in UTC
+    assert_eq!(
+        dates("DTSTART;TZID=Pacific/Auckland:20200101T010000\nRRULE:FREQ=MONTHLY;COUNT=3")?,
+        vec!["2020-01-01", "2020-02-01", "2020-03-01"]
+    );
+    Ok(())
+}
+
+/// Test every malformed entry is reported with its file rather than only the first
+#[async_std::test]
+async fn test_parse_errors() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_malformed"));
+    let mut errors: Vec<(String, String)> = ledger
+        .parse_errors()
+        .map(|(file, err)| (file, format!("{:#}", err)))
+        .collect()
+        .await;
+    errors.sort();
+    assert_eq!(errors.len(), 3, "{:#?}", errors);
+    assert!(errors[0].0.ends_with("2020-01.yaml"));
+    assert!(errors[0].1.contains("Amount required"), "{}", errors[0].1);
+    assert!(errors[1].0.ends_with("2020-01.yaml"));
+    assert!(errors[1].1.contains("Refund"), "{}", errors[1].1);
+    assert!(errors[2].0.ends_with("2020-02.yaml"));
+    Ok(())
+}
+
+/// Test cash flows are classified by the tags of their counter-accounts and net to the change
+/// in cash
+#[async_std::test]
+async fn test_cash_flow_statement() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_cash_flow"));
+    let chart = ChartOfAccounts::new(vec![
+        Account::new(Asset, "Business Checking", tags!["cash"]?),
+        Account::new(Liability, "Loan Payable", tags!["financing"]?),
+        Account::new(Asset, "Equipment", tags!["investing"]?),
+        Account::new(Revenue, "Widget Sales", tags!["operating"]?),
+        Account::new(Expense, "Rent", vec![]),
+        Account::new(Liability, "Accounts Payable", vec![]),
+        Account::new(Asset, "Accounts Receivable", vec![]),
+    ]);
+    let from = Some("2020-01-01".parse()?);
+    let to = Some("2020-12-31".parse()?);
+    let statement = ledger.cash_flow_statement(&chart, from, to).await?;
+    assert_eq!(statement.operating, 200.00.try_into()?);
+    assert_eq!(statement.investing, (-2000.00).try_into()?);
+    assert_eq!(statement.financing, 5000.00.try_into()?);
+
+    let cash = ledger
+        .balances_between(from, to, vec!["Business Checking".to_string()], None)
+        .await?;
+    assert_eq!(
+        cash.get("Business Checking")
+            .map(JournalAmount::to_signed_money),
+        Some(statement.net_change())
+    );
+    Ok(())
+}
+
+/// Test summing journal amounts nets debits against credits and errors on overflow
+#[async_std::test]
+async fn test_journal_amount_sum() -> Result<()> {
+    assert_eq!(JournalAmount::sum(Vec::new())?, JournalAmount::default());
+    let amounts = vec![
+        JournalAmount::Debit(100.00.try_into()?),
+        JournalAmount::Credit(150.00.try_into()?),
+        JournalAmount::Debit(20.00.try_into()?),
+    ];
+    assert_eq!(
+        JournalAmount::sum(amounts)?,
+        JournalAmount::Credit(30.00.try_into()?)
+    );
+    let huge = JournalAmount::Credit("79228162514264337593543950335".parse()?);
+    assert!(JournalAmount::sum(vec![huge, huge]).is_err());
+    Ok(())
+}
+
+/// Test streaming a date sorted dir matches collecting and sorting the journal
+#[async_std::test]
+async fn test_journal_sorted() -> Result<()> {
+    for dir in &[
+        "./tests/fixtures/entries_flat",
+        "./tests/fixtures/entries",
+        "./tests/fixtures/entries_recurring",
+    ] {
+        let ledger = Ledger::new(Some(dir));
+        let streamed: Vec<JournalEntry> = ledger
+            .journal_sorted(Vec::new(), None, None)
+            .try_collect()
+            .await?;
+        let mut collected: Vec<JournalEntry> = ledger.journal(None).try_collect().await?;
+        collected.sort_by_key(|x| x.0);
+        assert_eq!(streamed, collected);
+    }
+
+    // entries are read the same way, so duplicates are dropped here too
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_duplicated")).dedup();
+    let streamed: Vec<JournalEntry> = ledger
+        .journal_sorted(Vec::new(), None, None)
+        .try_collect()
+        .await?;
+    let mut collected: Vec<JournalEntry> = ledger.journal(None).try_collect().await?;
+    collected.sort_by_key(|x| x.0);
+    assert_eq!(streamed, collected);
+
+    let dir = unique_temp_dir("accounts_unsorted_journal");
+    std::fs::create_dir_all(&dir)?;
+    let doc = "---
+type: Payment Sent
+date: DATE
+party: ACME Business Services
+account: Business Checking
+amount: 1
+";
+    std::fs::write(dir.join("a.yaml"), doc.replace("DATE", "2020-02-01"))?;
+    std::fs::write(dir.join("b.yaml"), doc.replace("DATE", "2020-03-01"))?;
+    let ledger = Ledger::new(dir.to_str());
+    // out of order but after every line streamed so far so it still falls into place
+    std::fs::write(dir.join("c.yaml"), doc.replace("DATE", "2020-02-15"))?;
+    let streamed: Vec<JournalEntry> = ledger
+        .journal_sorted(Vec::new(), None, None)
+        .try_collect()
+        .await?;
+    assert!(streamed.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+    std::fs::write(dir.join("c.yaml"), doc.replace("DATE", "2020-01-01"))?;
+    let result = ledger
+        .journal_sorted(Vec::new(), None, None)
+        .try_collect::<Vec<_>>()
+        .await;
+    std::fs::remove_dir_all(&dir)?;
+    let err = result.unwrap_err();
+    assert!(err.to_string().contains("must be in date order"), "{}", err);
+    Ok(())
+}
+
+/// Test payments reduce the invoice they are allocated to or otherwise the oldest invoice
+#[async_std::test]
+async fn test_invoice_balances() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_allocated"));
+    let invoices = ledger.invoice_balances().await?;
+    dbg!(&invoices);
+    assert_eq!(invoices.len(), 2);
+    assert_eq!(invoices[0].id, "INV-1");
+    assert_eq!(invoices[0].amount, 60.00.try_into()?);
+    assert_eq!(invoices[1].id, "INV-2");
+    assert_eq!(invoices[1].amount, 0.00.try_into()?);
+    Ok(())
+}
+
+/// Test payments are capped at and only applied to a matching outstanding invoice
+#[async_std::test]
+async fn test_invoice_balances_misallocated() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_misallocated"));
+    let invoices = ledger.invoice_balances().await?;
+    assert_eq!(invoices.len(), 3);
+    assert_eq!(invoices[0].id, "INV-1");
+    assert_eq!(invoices[0].amount, 0.00.try_into()?);
+    assert_eq!(invoices[1].id, "INV-2");
+    assert_eq!(invoices[1].amount, 100.00.try_into()?);
+    assert_eq!(invoices[2].id, "INV-3");
+    assert_eq!(invoices[2].amount, 100.00.try_into()?);
+    // every payment whose reference matched no outstanding invoice is reported
+    let unmatched: Vec<String> = ledger
+        .unmatched_payments()
+        .into_iter()
+        .map(|(_, invoice)| invoice)
+        .collect();
+    assert_eq!(unmatched, vec!["INV-2", "INV-3", "INV-1", "INV-9"]);
+    assert!(ledger.unmatched_payments().is_empty());
+    Ok(())
+}
+
+/// Test a split payment settles what attached payments left open on invoices oldest first
+#[async_std::test]
+async fn test_split_payment_allocated() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries_split_allocated"));
+    let invoices = ledger.invoice_balances().await?;
+    assert_eq!(invoices.len(), 2);
+    assert_eq!(invoices[0].id, "INV-1");
+    assert_eq!(invoices[0].amount, 0.00.try_into()?);
+    assert_eq!(invoices[1].id, "INV-2");
+    assert_eq!(invoices[1].amount, 20.00.try_into()?);
+    Ok(())
+}
+
+/// Test journal amounts from signed money
+#[test]
+fn test_from_signed_money() -> Result<()> {
+    assert_eq!(
+        JournalAmount::from_signed_money(10.00.try_into()?),
+        JournalAmount::Debit(10.00.try_into()?)
+    );
+    assert_eq!(
+        JournalAmount::from_signed_money((-10.00).try_into()?),
+        JournalAmount::Credit(10.00.try_into()?)
+    );
+    assert_eq!(
+        JournalAmount::from_signed_money(0.00.try_into()?),
+        JournalAmount::default()
+    );
+    Ok(())
+}
+
+/// Test amounts of varying magnitude and precision line up on the decimal point
+#[test]
+fn test_row_alignment() -> Result<()> {
+    let amounts = vec![
+        JournalAmount::Debit(5.00.try_into()?),
+        JournalAmount::Debit(1250000.00.try_into()?),
+        JournalAmount::Debit(1.111.try_into()?),
+        JournalAmount::Credit(10.5.try_into()?),
+        JournalAmount::Credit(99999999.99.try_into()?),
+    ];
+    let format = RowFormat::fit(&amounts);
+    let rows: Vec<String> = amounts
+        .iter()
+        .map(|amount| amount.to_row_string(&format))
+        .collect();
+    dbg!(&rows);
+    assert!(rows.iter().map(|row| row.len()).all_equal());
+    assert!(rows[..3].iter().map(|row| row.find('.')).all_equal());
+    assert!(rows[3..].iter().map(|row| row.find('.')).all_equal());
+    Ok(())
+}
+
+/// Test invoice totals with and without a tax extra
+#[test]
+fn test_invoice_totals_with_extras() -> Result<()> {
+    let entry: Entry = "
+type: Sales Invoice
+date: 2020-01-05
+party: John Smith
+account: Widget Sales
+items:
+  - description: Widget
+    amount: 100
+extras:
+  - description: Sales Tax
+    account: Sales Tax Payable
+    rate: 0.08
+"
+    .parse()?;
+    assert_eq!(entry.items_subtotal()?, 100.00.try_into()?);
+    assert_eq!(entry.total_with_extras()?, 108.00.try_into()?);
+    assert_eq!(entry.abs_amount()?, 108.00.try_into()?);
+    // equal decimals can differ in scale so compare as rendered
+    assert_eq!(entry.total_with_extras()?.to_string(), "$108.00");
+
+    let date = "2020-01-05".parse()?;
+    let journal_entries = JournalEntry::from_entry(entry, Some(date))?;
+    let rendered = |lines: &[JournalEntry], account: &str| {
+        lines
+            .iter()
+            .find(|line| line.1 == account)
+            .map(|line| line.2.money().to_string())
+    };
+    assert_eq!(
+        rendered(&journal_entries, "Sales Tax Payable").as_deref(),
+        Some("$8.00")
+    );
+    assert_eq!(
+        rendered(&journal_entries, "Accounts Receivable").as_deref(),
+        Some("$108.00")
+    );
+    Expect(&journal_entries)
+        .contains("2020-01-05", "Widget Sales", Credit(100.00), "John Smith")
+        .contains(
+            "2020-01-05",
+            "Sales Tax Payable",
+            Credit(8.00),
+            "John Smith",
+        )
+        .contains(
+            "2020-01-05",
+            "Accounts Receivable",
+            Debit(108.00),
+            "John Smith",
+        );
+
+    // a rate on a subtotal that isn't round is rounded to cents with the invoice's rounding
+    let doc = "
+type: Sales Invoice
+date: 2020-01-05
+party: John Smith
+account: Widget Sales
+items:
+  - description: Widget
+    amount: 100.05
+extras:
+  - description: Sales Tax
+    account: Sales Tax Payable
+    rate: 0.08
+";
+    let entry: Entry = doc.parse()?;
+    assert_eq!(entry.total_with_extras()?.to_string(), "$108.05");
+    let journal_entries = JournalEntry::from_entry(entry, Some(date))?;
+    assert_eq!(
+        rendered(&journal_entries, "Sales Tax Payable").as_deref(),
+        Some("$8.00")
+    );
+    assert_eq!(
+        rendered(&journal_entries, "Accounts Receivable").as_deref(),
+        Some("$108.05")
+    );
+    let entry: Entry = format!("{}rounding: up\n", doc).parse()?;
+    assert_eq!(entry.total_with_extras()?.to_string(), "$108.06");
+    let entry: Entry = entry.to_yaml()?.parse()?;
+    assert_eq!(entry.abs_amount()?.to_string(), "$108.06");
+    Ok(())
+}
+
+/// Test GnuCash CSV export maps debits to deposits and credits to withdrawals
+#[async_std::test]
+async fn test_gnucash_csv() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let csv = ledger.to_gnucash_csv().await?;
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Date,Transaction ID,Description,Account,Deposit,Withdrawal")
+    );
+    let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+    assert_eq!(rows.len(), 16);
+    assert!(rows.iter().any(|row| row[0] == "2020-01-02"
+        && row[3] == "Credit Card"
+        && row[4].is_empty()
+        && row[5] == "100.00"));
+    assert!(rows.iter().any(|row| row[0] == "2020-01-02"
+        && row[3] == "Accounts Payable"
+        && row[4] == "100.00"
+        && row[5].is_empty()));
+    // both lines of the payment share a transaction id
+    let ids: Vec<&str> = rows
+        .iter()
+        .filter(|row| row[0] == "2020-01-02")
+        .map(|row| row[1])
+        .collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.iter().all_equal());
+    Ok(())
+}
+
+/// Benchmark balances over a large generated ledger
+#[async_std::test]
+async fn test_large_ledger_balances() -> Result<()> {
+    let count = 10_000;
+    let dir = unique_temp_dir("accounts_test_large_ledger");
+    std::fs::create_dir_all(&dir)?;
+    let doc = "---
+type: Purchase Invoice
+date: 2020-01-01
+party: ACME Business Services
+account: Operating Expenses
+items:
+  - amount: 1
+---
+type: Payment Sent
+date: 2020-01-02
+party: ACME Business Services
+account: Business Checking
+amount: 1
+";
+    std::fs::write(dir.join("ledger.yaml"), doc.repeat(count))?;
+
+    let start = std::time::Instant::now();
+    let ledger = Ledger::new(dir.to_str());
+    let balances = ledger.balances(None).await?;
+    println!("balances of {} entries in {:?}", count * 2, start.elapsed());
+    std::fs::remove_dir_all(&dir)?;
+
+    Expect(&balances)
+        .contains("Operating Expenses", Debit(count as f64))
+        .contains("Business Checking", Credit(count as f64))
+        .contains("Accounts Payable", Debit(0.00));
+    Ok(())
+}
+
 /// Test journal entries from recurring entries
 #[async_std::test]
 async fn test_recurring() -> Result<()> {
@@ -249,6 +2063,33 @@ async fn test_chart_of_accounts() -> Result<()> {
     Ok(())
 }
 
+/// Test a generated chart has every account in the ledger and can be read back
+#[async_std::test]
+async fn test_init_chart() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let chart = ledger.init_chart().await?;
+    let balances = ledger.balances(None).await?;
+    assert_eq!(chart.accounts().len(), balances.len());
+    for account in balances.keys() {
+        chart.get(account)?;
+    }
+    assert_eq!(chart.get("Operating Expenses")?.acc_type, Expense);
+    assert_eq!(chart.get("Credit Card")?.acc_type, Liability);
+    assert_eq!(chart.get("Business Checking")?.acc_type, Asset);
+    assert_eq!(chart.get("Widget Sales")?.acc_type, Revenue);
+    assert_eq!(chart.get("Accounts Payable")?.acc_type, Liability);
+    assert_eq!(chart.get("Accounts Receivable")?.acc_type, Asset);
+
+    let yaml = chart.to_yaml()?;
+    let accounts = yaml
+        .split("---\n")
+        .filter(|doc| !doc.is_empty())
+        .map(|doc| doc.parse())
+        .collect::<Result<Vec<Account>>>()?;
+    assert_eq!(accounts.len(), balances.len());
+    Ok(())
+}
+
 #[async_std::test]
 async fn test_report() -> Result<()> {
     let report = ReportNode::from_file("./tests/fixtures/IncomeStatement.yaml").await?;
@@ -280,7 +2121,9 @@ async fn test_run_report() -> Result<()> {
     let chart_of_accounts =
         ChartOfAccounts::from_file("./tests/fixtures/ChartOfAccounts.yaml").await?;
     let mut report = ReportNode::from_file("./tests/fixtures/IncomeStatement.yaml").await?;
-    ledger.run_report(&chart_of_accounts, &mut report).await?;
+    ledger
+        .run_report(&chart_of_accounts, &mut report, None)
+        .await?;
     let items = report.items()?;
     dbg!(&items);
     println!("{}", report);
@@ -317,6 +2160,31 @@ async fn test_run_report() -> Result<()> {
     Ok(())
 }
 
+/// Test running a report on balances as of a date before the last entries
+#[async_std::test]
+async fn test_run_report_as_of() -> Result<()> {
+    let ledger = Ledger::new(Some("./tests/fixtures/entries"));
+    let chart_of_accounts =
+        ChartOfAccounts::from_file("./tests/fixtures/ChartOfAccounts.yaml").await?;
+    let mut full = ReportNode::from_file("./tests/fixtures/IncomeStatement.yaml").await?;
+    ledger
+        .run_report(&chart_of_accounts, &mut full, None)
+        .await?;
+    let mut as_of = ReportNode::from_file("./tests/fixtures/IncomeStatement.yaml").await?;
+    ledger
+        .run_report(&chart_of_accounts, &mut as_of, Some("2020-01-05".parse()?))
+        .await?;
+    let (full, as_of) = (full.items()?, as_of.items()?);
+
+    // expenses were all entered by then but only the first sale
+    assert_eq!(as_of[4].2 .1, full[4].2 .1);
+    assert_eq!(as_of[6].0, vec!["Income Statement", "Revenue"]);
+    assert_eq!(as_of[6].2 .1, JournalAmount::Credit(10.00.try_into()?));
+    assert_eq!(full[6].2 .1, JournalAmount::Credit(25.00.try_into()?));
+    assert_eq!(as_of[0].2 .1, JournalAmount::Debit(240.00.try_into()?));
+    Ok(())
+}
+
 #[derive(Debug)]
 enum JournalAmountTest {
     Debit(f64),
@@ -336,9 +2204,14 @@ impl Expect<'_, Vec<JournalEntry>> {
                 Credit(m) => JournalAmount::Credit(m.try_into().unwrap()),
             },
             Some(party.to_owned()),
+            None,
         );
+        // any reference of the entry aside
         assert!(
-            self.0.iter().any(|actual| actual == expected),
+            self.0.iter().any(|actual| &JournalEntry {
+                4: None,
+                ..actual.clone()
+            } == expected),
             "{:?} not found in {:?}",
             expected,
             self.0
@@ -365,3 +2238,10 @@ impl Expect<'_, HashMap<JournalAccount, JournalAmount>> {
         self
     }
 }
+
+/// A temp dir unique to this test process and call so concurrent runs don't collide
+fn unique_temp_dir(name: &str) -> std::path::PathBuf {
+    static COUNT: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let count = COUNT.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    std::env::temp_dir().join(format!("{}_{}_{}", name, std::process::id(), count))
+}